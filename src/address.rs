@@ -0,0 +1,145 @@
+//! Cross-chain conversion of recipient addresses.
+use std::{collections::BTreeSet, str::FromStr};
+
+use eyre::{eyre, Result};
+use ocular::cosmrs::AccountId;
+
+use crate::payments::Payment;
+
+/// The SLIP-44 coin type used by most Cosmos SDK chains.
+pub const DEFAULT_COIN_TYPE: u32 = 118;
+
+/// Known chains that derive keys with a coin type other than [`DEFAULT_COIN_TYPE`], by bech32
+/// prefix. Re-encoding an address to or from one of these yields an address the recipient's
+/// wallet will not derive from the same mnemonic.
+const COIN_TYPES: &[(&str, u32)] = &[
+    ("band", 494),
+    ("canto", 60),
+    ("cro", 394),
+    ("evmos", 60),
+    ("inj", 60),
+    ("kava", 459),
+    ("secret", 529),
+    ("terra", 330),
+];
+
+/// Returns the coin type used by the chain with the given bech32 prefix.
+pub fn coin_type(prefix: &str) -> u32 {
+    COIN_TYPES
+        .iter()
+        .find(|(p, _)| *p == prefix)
+        .map(|(_, c)| *c)
+        .unwrap_or(DEFAULT_COIN_TYPE)
+}
+
+/// How recipient addresses whose prefix doesn't match the target chain are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrefixPolicy {
+    /// Fail on any recipient with a different prefix
+    Reject,
+    /// Re-encode the recipient's address bytes with the target prefix
+    Convert,
+}
+
+/// Payments with recipients normalized to a single prefix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NormalizedRecipients {
+    pub payments: Vec<Payment>,
+    /// Number of recipients whose address was re-encoded
+    pub converted: usize,
+    /// Coin type compatibility warnings, one per source prefix
+    pub warnings: Vec<String>,
+}
+
+/// Re-encodes a bech32 address with a different human-readable prefix.
+pub fn convert_address(address: &str, prefix: &str) -> Result<String> {
+    let id = AccountId::from_str(address)?;
+
+    Ok(AccountId::new(prefix, &id.to_bytes())?.to_string())
+}
+
+/// Checks every recipient against `prefix`, converting or rejecting mismatches according to
+/// `policy`.
+pub fn normalize_recipients(
+    payments: Vec<Payment>,
+    prefix: &str,
+    policy: PrefixPolicy,
+) -> Result<NormalizedRecipients> {
+    let mut converted = 0;
+    let mut source_prefixes = BTreeSet::<String>::new();
+    let mut output = Vec::<Payment>::with_capacity(payments.len());
+    for mut p in payments {
+        let id = AccountId::from_str(&p.recipient)?;
+        if id.prefix() != prefix {
+            if policy == PrefixPolicy::Reject {
+                return Err(eyre!(
+                    "recipient {} does not have the chain's address prefix {}",
+                    p.recipient,
+                    prefix
+                ));
+            }
+
+            source_prefixes.insert(id.prefix().to_string());
+            p.recipient = AccountId::new(prefix, &id.to_bytes())?.to_string();
+            converted += 1;
+        }
+        output.push(p);
+    }
+
+    let target_coin_type = coin_type(prefix);
+    let warnings = source_prefixes
+        .iter()
+        .filter(|p| coin_type(p) != target_coin_type)
+        .map(|p| {
+            format!(
+                "converted {} addresses use coin type {} but {} uses coin type {}; recipients may not control the converted addresses",
+                p,
+                coin_type(p),
+                prefix,
+                target_coin_type
+            )
+        })
+        .collect();
+
+    Ok(NormalizedRecipients {
+        payments: output,
+        converted,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(recipient: &str) -> Payment {
+        Payment {
+            recipient: recipient.to_string(),
+            amount: 1,
+            denom: "uosmo".to_string(),
+        }
+    }
+
+    #[test]
+    fn converts_recipient_prefixes() {
+        let payments = vec![
+            payment("cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08"),
+            payment("evmos1n6j7gnld9yxfyh6tflxhjjmt404zruuatlq940"),
+        ];
+
+        assert!(normalize_recipients(payments.clone(), "osmo", PrefixPolicy::Reject).is_err());
+
+        let result = normalize_recipients(payments, "osmo", PrefixPolicy::Convert).unwrap();
+        let expected = "osmo1n6j7gnld9yxfyh6tflxhjjmt404zruuap9zme4";
+
+        assert_eq!(result.converted, 2);
+        assert_eq!(
+            convert_address("cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08", "osmo").unwrap(),
+            expected
+        );
+        assert_eq!(result.payments[0].recipient, expected);
+        assert_eq!(result.payments[1].recipient, expected);
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].starts_with("converted evmos"));
+    }
+}
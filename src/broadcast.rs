@@ -0,0 +1,383 @@
+//! Signing, sync-mode broadcasting and confirmation of transactions.
+//!
+//! Transactions are submitted with `broadcast_tx_sync`, which returns once they pass `CheckTx`,
+//! and then polled for by hash until they are included in a block or the confirmation times out.
+//! This replaces `broadcast_tx_commit`, which is deprecated in CometBFT and times out on slow
+//! blocks without saying whether the transaction landed. Transient failures are retried
+//! according to a [`RetryPolicy`].
+use std::{str::FromStr, time::Duration};
+
+use eyre::{eyre, Report, Result};
+use ocular::{
+    chain::Context,
+    cosmrs::{
+        rpc::{Client, HttpClient},
+        tendermint::{
+            abci::{transaction::Hash, Event, Transaction},
+            block::Height,
+            chain,
+        },
+        tx::{Body, Fee, SignDoc, SignerInfo},
+        Any,
+    },
+    prelude::AccountInfo,
+    tx::FeeInfo,
+};
+use sha2::{Digest, Sha256};
+use tokio::time::{sleep, Instant};
+
+use crate::{
+    grpc,
+    retry::{classify, Failure, RetryPolicy},
+};
+
+/// Error returned by nodes that already hold the transaction in their mempool
+const TX_IN_CACHE: &str = "tx already exists in cache";
+
+/// Shortest interval between checks for a transaction, however the options are set
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for a broadcast transaction to be included in a block, and how often to
+/// check. The interval between checks grows by `backoff_factor` up to `max_interval`. A factor
+/// below 1 counts as 1, and intervals are never shorter than 100ms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfirmationOptions {
+    pub timeout: Duration,
+    pub initial_interval: Duration,
+    pub max_interval: Duration,
+    pub backoff_factor: u32,
+}
+
+impl Default for ConfirmationOptions {
+    fn default() -> Self {
+        ConfirmationOptions {
+            timeout: Duration::from_secs(60),
+            initial_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(6),
+            backoff_factor: 2,
+        }
+    }
+}
+
+impl ConfirmationOptions {
+    /// The intervals between successive checks
+    fn intervals(&self) -> impl Iterator<Item = Duration> + '_ {
+        let max_interval = self.max_interval.max(MIN_POLL_INTERVAL);
+        let initial_interval = self.initial_interval.max(MIN_POLL_INTERVAL);
+        std::iter::successors(Some(initial_interval), move |i| {
+            let next = i.checked_mul(self.backoff_factor.max(1));
+            Some(next.unwrap_or(max_interval).min(max_interval))
+        })
+    }
+}
+
+/// The result of a transaction included in a block.
+#[derive(Clone, Debug)]
+pub struct TxConfirmation {
+    /// Upper-case hex transaction hash
+    pub hash: String,
+    pub height: u64,
+    /// Zero if the transaction succeeded
+    pub code: u32,
+    pub codespace: String,
+    pub log: String,
+    pub gas_wanted: u64,
+    pub gas_used: u64,
+    pub events: Vec<Event>,
+    /// Checks that had to be skipped before the transaction was signed
+    pub warnings: Vec<String>,
+}
+
+impl TxConfirmation {
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+
+    /// Returns the confirmation, or an error carrying its code and log if the transaction failed.
+    pub fn into_result(self) -> Result<TxConfirmation> {
+        if !self.is_ok() {
+            return Err(eyre!(
+                "transaction {} failed with code {} ({}): {}",
+                self.hash,
+                self.code,
+                self.codespace,
+                self.log
+            ));
+        }
+
+        Ok(self)
+    }
+}
+
+/// Fetches the account number and next sequence of `address`. Module, vesting and `EthAccount`
+/// accounts are read through the base account they wrap.
+pub async fn account_sequence(
+    grpc_endpoint: &str,
+    address: &str,
+    retry: &RetryPolicy,
+) -> Result<(u64, u64)> {
+    let account = retry
+        .run(|| grpc::account(grpc_endpoint, address))
+        .await?
+        .ok_or_else(|| eyre!("account {} does not exist", address))?;
+    let account = grpc::base_account(&account)?.ok_or_else(|| {
+        eyre!(
+            "account {} is a {}, which has no base account to sign with",
+            address,
+            account.type_url
+        )
+    })?;
+
+    Ok((account.account_number, account.sequence))
+}
+
+/// Signs `msgs` into the bytes of a transaction using the given account number and sequence. With
+/// a `timeout_height`, the chain rejects the transaction once that height has passed.
+///
+/// As with ocular's `UnsignedTx`, the memo is left empty. The fee amount, gas limit, fee payer
+/// and fee granter are all taken from `fee_info`.
+pub fn sign_tx(
+    signer: &AccountInfo,
+    msgs: Vec<Any>,
+    fee_info: &FeeInfo,
+    chain_id: &str,
+    account_number: u64,
+    sequence: u64,
+    timeout_height: Option<u64>,
+) -> Result<Vec<u8>> {
+    let key = signer.private_key();
+    let body = Body::new(msgs, "", Height::try_from(timeout_height.unwrap_or(0))?);
+    let fee = Fee {
+        amount: vec![fee_info.get_fee()],
+        gas_limit: fee_info.get_gas_limit().into(),
+        payer: fee_info.get_fee_payer(),
+        granter: fee_info.get_fee_granter(),
+    };
+    let auth_info = SignerInfo::single_direct(Some(key.public_key()), sequence).auth_info(fee);
+    let sign_doc = SignDoc::new(
+        &body,
+        &auth_info,
+        &chain::Id::from_str(chain_id)?,
+        account_number,
+    )?;
+
+    sign_doc.sign(key)?.to_bytes()
+}
+
+/// Submits a signed transaction in sync mode, returning its hash once it passes `CheckTx`.
+pub async fn broadcast_sync(rpc_endpoint: &str, tx_bytes: Vec<u8>) -> Result<String> {
+    let response = HttpClient::new(rpc_endpoint)?
+        .broadcast_tx_sync(Transaction::from(tx_bytes))
+        .await?;
+    if response.code.is_err() {
+        return Err(eyre!(
+            "transaction {} was rejected with code {}: {}",
+            response.hash,
+            response.code.value(),
+            response.log
+        ));
+    }
+
+    Ok(response.hash.to_string())
+}
+
+/// Whether a broadcast failed because `CheckTx` rejected the transaction for good, so it can't be
+/// in any mempool. A full mempool is only a rejection by that node, and isn't counted.
+pub fn is_check_tx_rejection(error: &Report) -> bool {
+    format!("{:#}", error).contains("was rejected with code") && classify(error) == Failure::Unsafe
+}
+
+/// Returns the upper-case hex hash of a signed transaction, as reported by Tendermint.
+pub fn tx_hash(tx_bytes: &[u8]) -> String {
+    hex::encode_upper(Sha256::digest(tx_bytes))
+}
+
+/// Whether a `tx` query failed because the node has no such transaction, rather than because the
+/// node couldn't answer
+fn is_tx_not_found(message: &str) -> bool {
+    message.contains("not found")
+}
+
+/// Whether the transaction with hex hash `hash` has been included in a block.
+async fn tx_included(rpc_endpoint: &str, hash: &str) -> Result<bool> {
+    let client = HttpClient::new(rpc_endpoint)?;
+
+    match client.tx(Hash::from_str(hash)?, false).await {
+        Ok(_) => Ok(true),
+        Err(e) if is_tx_not_found(&e.to_string()) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Like [`broadcast_sync`], but retries failures that can't have changed any state. Before each
+/// retry the chain is checked for the transaction, so it is never broadcast again once included.
+/// If every attempt fails the error says whether the transaction may still have been accepted.
+pub async fn broadcast_with_retry(
+    rpc_endpoint: &str,
+    tx_bytes: Vec<u8>,
+    retry: &RetryPolicy,
+) -> Result<String> {
+    let hash = tx_hash(&tx_bytes);
+    let mut attempt = 0;
+    loop {
+        if attempt > 0 {
+            match tx_included(rpc_endpoint, &hash).await {
+                Ok(true) => return Ok(hash),
+                Ok(false) => {}
+                // The broadcast below fails the same way and is retried
+                Err(e) if classify(&e) == Failure::Safe => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let error = match broadcast_sync(rpc_endpoint, tx_bytes.clone()).await {
+            Ok(hash) => return Ok(hash),
+            Err(e) if e.to_string().contains(TX_IN_CACHE) => return Ok(hash),
+            Err(e) => e,
+        };
+
+        attempt += 1;
+        if classify(&error) == Failure::Unsafe {
+            return Err(error);
+        }
+        if attempt >= retry.max_attempts {
+            return Err(error.wrap_err(format!(
+                "gave up broadcasting transaction {} after {} attempts; it is not on chain yet \
+                 but may still be in a mempool, so check it before signing it again",
+                hash, attempt
+            )));
+        }
+
+        sleep(retry.backoff(attempt - 1)).await;
+    }
+}
+
+/// Polls for the transaction with hex hash `hash` until it is included in a block, giving up
+/// after `options.timeout`. Fails straight away if the node can't be queried, so an unreachable
+/// node isn't mistaken for a dropped transaction.
+pub async fn wait_for_tx(
+    rpc_endpoint: &str,
+    hash: &str,
+    options: &ConfirmationOptions,
+) -> Result<TxConfirmation> {
+    let client = HttpClient::new(rpc_endpoint)?;
+    let parsed = Hash::from_str(hash)?;
+    let deadline = Instant::now() + options.timeout;
+    for interval in options.intervals() {
+        match client.tx(parsed, false).await {
+            Ok(response) => {
+                let result = response.tx_result;
+                return Ok(TxConfirmation {
+                    hash: response.hash.to_string(),
+                    height: response.height.value(),
+                    code: result.code.value(),
+                    codespace: result.codespace.as_ref().to_string(),
+                    log: result.log.to_string(),
+                    gas_wanted: result.gas_wanted.value(),
+                    gas_used: result.gas_used.value(),
+                    events: result.events,
+                    warnings: Vec::new(),
+                });
+            }
+            Err(e) if is_tx_not_found(&e.to_string()) => {}
+            Err(e) => {
+                return Err(eyre!(
+                    "failed to query transaction {} from {}: {}",
+                    hash,
+                    rpc_endpoint,
+                    e
+                ))
+            }
+        }
+
+        if Instant::now() + interval > deadline {
+            break;
+        }
+        sleep(interval).await;
+    }
+
+    Err(eyre!(
+        "timed out after {:?} waiting for transaction {} to be included in a block",
+        options.timeout,
+        hash
+    ))
+}
+
+/// Broadcasts a signed transaction in sync mode and waits for it to be included in a block.
+pub async fn broadcast_and_confirm(
+    rpc_endpoint: &str,
+    tx_bytes: Vec<u8>,
+    options: &ConfirmationOptions,
+    retry: &RetryPolicy,
+) -> Result<TxConfirmation> {
+    let hash = broadcast_with_retry(rpc_endpoint, tx_bytes, retry).await?;
+    wait_for_tx(rpc_endpoint, &hash, options).await
+}
+
+/// Signs `msgs` into a single transaction with the signer's current sequence, broadcasts it and
+/// waits for it to be included in a block. Returns an error if the transaction fails.
+#[allow(clippy::too_many_arguments)]
+pub async fn sign_and_confirm(
+    signer: &AccountInfo,
+    msgs: Vec<Any>,
+    fee_info: &FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+    options: &ConfirmationOptions,
+    retry: &RetryPolicy,
+) -> Result<TxConfirmation> {
+    let address = signer.address(&chain_context.prefix)?;
+    let (account_number, sequence) = account_sequence(grpc_endpoint, &address, retry).await?;
+    let tx_bytes = sign_tx(
+        signer,
+        msgs,
+        fee_info,
+        &chain_context.id,
+        account_number,
+        sequence,
+        None,
+    )?;
+
+    broadcast_and_confirm(rpc_endpoint, tx_bytes, options, retry)
+        .await?
+        .into_result()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_up_to_max_interval() {
+        let options = ConfirmationOptions::default();
+        let intervals: Vec<u64> = options.intervals().take(5).map(|i| i.as_secs()).collect();
+
+        assert_eq!(intervals, vec![1, 2, 4, 6, 6]);
+
+        let zeroed = ConfirmationOptions {
+            initial_interval: Duration::ZERO,
+            backoff_factor: 0,
+            ..Default::default()
+        };
+        assert!(zeroed.intervals().take(3).all(|i| i >= MIN_POLL_INTERVAL));
+
+        assert!(is_tx_not_found(
+            "Internal error: tx (9F86D081884C7D65) not found"
+        ));
+        assert!(!is_tx_not_found(
+            "error trying to connect: Connection refused"
+        ));
+    }
+
+    #[test]
+    fn tells_check_tx_rejections_from_other_broadcast_failures() {
+        assert!(is_check_tx_rejection(&eyre!(
+            "transaction ABCD was rejected with code 5: insufficient funds"
+        )));
+        assert!(!is_check_tx_rejection(&eyre!(
+            "transaction ABCD was rejected with code 20: mempool is full"
+        )));
+        assert!(!is_check_tx_rejection(&eyre!("operation timed out")));
+    }
+}
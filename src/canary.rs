@@ -0,0 +1,321 @@
+//! Canary runs that check a small part of an airdrop on chain before sending the rest.
+//!
+//! The canary payments are sent and confirmed first, and the recipients' balances are compared
+//! before and after. Only if every canary transaction succeeded and every balance moved by
+//! exactly the planned amount are the remaining payments sent; otherwise the airdrop stops with a
+//! report of what differed.
+use std::collections::{BTreeMap, BTreeSet};
+
+use eyre::{eyre, Result};
+use ocular::{
+    chain::Context, cosmrs::proto::cosmos::base::v1beta1::Coin, prelude::AccountInfo, tx::FeeInfo,
+};
+
+use crate::{
+    broadcast::TxConfirmation,
+    executor::{ExecutionError, ExecutionReport, Executor},
+    grpc,
+    ibc::resolve_denom,
+    multi_send_batches,
+    payments::Payment,
+    sign_and_broadcast_batches,
+};
+
+/// Which payments make up the canary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Canary {
+    /// The first `count` payments of the airdrop, which are then not sent again
+    Subset { count: usize },
+    /// `amount` of every airdropped denom to each of `recipients`, usually addresses the team
+    /// controls, in addition to the airdrop itself
+    TestAmount {
+        recipients: Vec<String>,
+        amount: u64,
+    },
+}
+
+impl Canary {
+    /// Splits `payments` into the canary payments and those sent after it. Returns an error if the
+    /// canary would be empty, since it would then pass without checking anything.
+    pub fn split(&self, payments: Vec<Payment>) -> Result<(Vec<Payment>, Vec<Payment>)> {
+        let (canary, remaining) = match self {
+            Canary::Subset { count } => {
+                let mut canary = payments;
+                let remaining = canary.split_off((*count).min(canary.len()));
+                (canary, remaining)
+            }
+            Canary::TestAmount { recipients, amount } => {
+                let denoms: BTreeSet<&str> = payments.iter().map(|p| p.denom.as_str()).collect();
+                let canary = recipients
+                    .iter()
+                    .flat_map(|r| {
+                        denoms.iter().map(move |d| Payment {
+                            recipient: r.clone(),
+                            amount: *amount,
+                            denom: d.to_string(),
+                        })
+                    })
+                    .collect();
+                (canary, payments)
+            }
+        };
+        if canary.is_empty() {
+            return Err(eyre!(
+                "the canary has no payments, so it would check nothing"
+            ));
+        }
+
+        Ok((canary, remaining))
+    }
+}
+
+/// A recipient whose balance didn't move by the planned amount.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceMismatch {
+    pub recipient: String,
+    pub denom: String,
+    pub expected: u128,
+    /// Signed change in the balance
+    pub actual: i128,
+}
+
+/// What the canary sent and how the balances compared.
+#[derive(Clone, Debug, Default)]
+pub struct CanaryReport {
+    pub payments: Vec<Payment>,
+    /// Confirmations of the canary transactions that were included, including failed ones
+    pub confirmations: Vec<TxConfirmation>,
+    /// Why the canary stopped, if a transaction was rejected or failed or the balances after it
+    /// couldn't be read
+    pub failed: Option<String>,
+    pub mismatches: Vec<BalanceMismatch>,
+}
+
+impl CanaryReport {
+    pub fn passed(&self) -> bool {
+        self.failed.is_none() && self.mismatches.is_empty()
+    }
+}
+
+/// The outcome of [`execute_airdrop_with_canary`]. `execution` is `None` if the canary failed.
+#[derive(Clone, Debug, Default)]
+pub struct CanaryAirdropReport {
+    pub canary: CanaryReport,
+    pub execution: Option<ExecutionReport>,
+}
+
+/// Total each recipient should receive per on-chain denom
+fn expected_deltas(payments: &[Payment]) -> BTreeMap<(String, String), u128> {
+    let mut deltas = BTreeMap::<(String, String), u128>::new();
+    for p in payments {
+        *deltas
+            .entry((p.recipient.clone(), resolve_denom(&p.denom)))
+            .or_insert(0) += p.amount as u128;
+    }
+
+    deltas
+}
+
+fn amount_of(balances: &[Coin], denom: &str) -> u128 {
+    balances
+        .iter()
+        .filter(|c| c.denom == denom)
+        .filter_map(|c| c.amount.parse::<u128>().ok())
+        .sum()
+}
+
+/// Compares the balance changes of every canary recipient with the planned amounts.
+fn balance_mismatches(
+    payments: &[Payment],
+    before: &BTreeMap<String, Vec<Coin>>,
+    after: &BTreeMap<String, Vec<Coin>>,
+) -> Vec<BalanceMismatch> {
+    let empty = Vec::new();
+    expected_deltas(payments)
+        .into_iter()
+        .filter_map(|((recipient, denom), expected)| {
+            let before = amount_of(before.get(&recipient).unwrap_or(&empty), &denom);
+            let after = amount_of(after.get(&recipient).unwrap_or(&empty), &denom);
+            let actual = after as i128 - before as i128;
+            if actual == expected as i128 {
+                return None;
+            }
+
+            Some(BalanceMismatch {
+                recipient,
+                denom,
+                expected,
+                actual,
+            })
+        })
+        .collect()
+}
+
+async fn recipient_balances(
+    grpc_endpoint: &str,
+    payments: &[Payment],
+    height: Option<u64>,
+) -> Result<BTreeMap<String, Vec<Coin>>> {
+    let recipients: BTreeSet<&str> = payments.iter().map(|p| p.recipient.as_str()).collect();
+    let mut balances = BTreeMap::<String, Vec<Coin>>::new();
+    for recipient in recipients {
+        balances.insert(
+            recipient.to_string(),
+            grpc::balances(grpc_endpoint, recipient, height).await?,
+        );
+    }
+
+    Ok(balances)
+}
+
+/// Sends the canary's payments, checks the recipients' balances moved by exactly the planned
+/// amounts, and only then sends the remaining payments in batches of `batch_size`. A canary
+/// transaction that is rejected or fails stops the airdrop with a report rather than an error;
+/// errors before anything is broadcast are returned as they are. Once the canary has passed, a
+/// failed transaction in the rest of the airdrop is returned as an [`ExecutionError`] carrying
+/// its report.
+///
+/// The sender must not be a canary recipient, since the fees it pays would show as a mismatch.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_airdrop_with_canary(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    canary: &Canary,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<CanaryAirdropReport> {
+    let address = sender.address(&chain_context.prefix)?;
+    let (canary_payments, remaining) = canary.split(payments)?;
+    let before = recipient_balances(grpc_endpoint, &canary_payments, None).await?;
+    let sent = sign_and_broadcast_batches(
+        sender,
+        multi_send_batches(&address, canary_payments.clone(), batch_size)?,
+        fee_info.clone(),
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await;
+    // A canary transaction that was rejected or failed is reported along with the balances
+    let (confirmations, failed) = match sent {
+        Ok(confirmations) => (confirmations, None),
+        Err(e) => {
+            let failed = format!("{:#}", e);
+            match e.downcast::<ExecutionError>() {
+                Ok(error) => (error.report.confirmations, Some(failed)),
+                Err(e) => return Err(e),
+            }
+        }
+    };
+    // Read at the canary's last block, so a node that hasn't reached it errors rather than
+    // showing balances from before the canary. The canary has been sent by now, so a failed read
+    // stops the airdrop with the report.
+    let height = confirmations.iter().map(|c| c.height).max();
+    let (mismatches, failed) = match recipient_balances(grpc_endpoint, &canary_payments, height)
+        .await
+    {
+        Ok(after) => (
+            balance_mismatches(&canary_payments, &before, &after),
+            failed,
+        ),
+        Err(e) => (
+            Vec::new(),
+            failed.or_else(|| Some(format!("couldn't read balances after the canary: {:#}", e))),
+        ),
+    };
+
+    let mut report = CanaryAirdropReport {
+        canary: CanaryReport {
+            mismatches,
+            payments: canary_payments,
+            confirmations,
+            failed,
+        },
+        execution: None,
+    };
+    if !report.canary.passed() {
+        return Ok(report);
+    }
+
+    let executor = Executor::new(sender, fee_info, chain_context, rpc_endpoint, grpc_endpoint);
+    report.execution = Some(
+        executor
+            .execute(multi_send_batches(&address, remaining, batch_size)?)
+            .await
+            .and_then(ExecutionReport::into_result)?,
+    );
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(recipient: &str, amount: u64, denom: &str) -> Payment {
+        Payment {
+            recipient: recipient.to_string(),
+            amount,
+            denom: denom.to_string(),
+        }
+    }
+
+    fn coin(amount: u128, denom: &str) -> Coin {
+        Coin {
+            denom: denom.to_string(),
+            amount: amount.to_string(),
+        }
+    }
+
+    #[test]
+    fn reports_unexpected_balance_changes() {
+        let payments = vec![
+            payment("alice", 10, "uatom"),
+            payment("bob", 20, "uatom"),
+            payment("carol", 30, "uatom"),
+        ];
+        let (canary, remaining) = Canary::Subset { count: 2 }.split(payments.clone()).unwrap();
+        assert_eq!((canary.len(), remaining.len()), (2, 1));
+
+        let before = BTreeMap::from([("alice".to_string(), vec![coin(5, "uatom")])]);
+        let after = BTreeMap::from([
+            ("alice".to_string(), vec![coin(15, "uatom")]),
+            ("bob".to_string(), vec![coin(20, "uosmo")]),
+        ]);
+        let mismatches = balance_mismatches(&canary, &before, &after);
+
+        assert_eq!(
+            mismatches,
+            vec![BalanceMismatch {
+                recipient: "bob".to_string(),
+                denom: "uatom".to_string(),
+                expected: 20,
+                actual: 0,
+            }]
+        );
+
+        let test_amount = Canary::TestAmount {
+            recipients: vec!["team".to_string()],
+            amount: 1,
+        };
+        let (canary, remaining) = test_amount.split(payments.clone()).unwrap();
+        assert_eq!(canary, vec![payment("team", 1, "uatom")]);
+        assert_eq!(remaining.len(), 3);
+        assert!(Canary::Subset { count: 0 }.split(payments).is_err());
+        let no_recipients = Canary::TestAmount {
+            recipients: Vec::new(),
+            amount: 1,
+        };
+        assert!(no_recipients.split(Vec::new()).is_err());
+
+        let rejected = CanaryReport {
+            failed: Some("insufficient funds".to_string()),
+            ..Default::default()
+        };
+        assert!(!rejected.passed());
+        assert!(CanaryReport::default().passed());
+    }
+}
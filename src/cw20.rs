@@ -0,0 +1,231 @@
+//! Airdrops of CW20 tokens through `MsgExecuteContract`.
+//!
+//! A [`Payment`] pays a CW20 token when its denom is the token contract's address prefixed with
+//! `cw20:`, e.g. `cw20:juno1...`. Each payment becomes a `transfer` execution, and payments are
+//! batched into transactions of at most `batch_size` messages.
+use std::{collections::BTreeMap, str::FromStr};
+
+use eyre::{eyre, Result};
+use ocular::{
+    chain::Context,
+    cosmrs::{AccountId, Any},
+    prelude::{AccountInfo, Authz},
+    tx::{FeeInfo, ModuleMsg},
+};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    broadcast::TxConfirmation,
+    grpc,
+    payments::{batch_payments, Payment},
+    proto::{to_any, MsgExecuteContract},
+    sign_and_broadcast_batches,
+};
+
+/// Denom prefix marking a payment as a CW20 token
+pub const CW20_PREFIX: &str = "cw20:";
+
+const MSG_EXECUTE_CONTRACT_TYPE_URL: &str = "/cosmwasm.wasm.v1.MsgExecuteContract";
+
+/// Returns the token contract address if `denom` refers to a CW20 token.
+pub fn cw20_contract(denom: &str) -> Option<&str> {
+    denom.strip_prefix(CW20_PREFIX)
+}
+
+/// Builds a `MsgExecuteContract` running `msg` on `contract` without attached funds.
+pub(crate) fn execute_contract_msg(
+    sender: &str,
+    contract: &str,
+    msg: &serde_json::Value,
+) -> Result<Any> {
+    let msg = MsgExecuteContract {
+        sender: sender.to_string(),
+        contract: contract.to_string(),
+        msg: serde_json::to_vec(msg)?,
+        funds: Vec::new(),
+    };
+
+    Ok(to_any(MSG_EXECUTE_CONTRACT_TYPE_URL, &msg))
+}
+
+/// Builds one CW20 `transfer` execution per payment. Every payment must be a CW20 payment and its
+/// recipient must share the sender's address prefix.
+pub fn transfer_msgs_from_payments(sender_address: &str, payments: &[Payment]) -> Result<Vec<Any>> {
+    let sender = AccountId::from_str(sender_address)?;
+    payments
+        .iter()
+        .map(|p| {
+            let contract = cw20_contract(&p.denom)
+                .ok_or_else(|| eyre!("payment to {} is not a CW20 payment", p.recipient))?;
+            if AccountId::from_str(&p.recipient)?.prefix() != sender.prefix() {
+                return Err(eyre!(
+                    "recipient {} does not match the sender's address prefix {}",
+                    p.recipient,
+                    sender.prefix()
+                ));
+            }
+
+            execute_contract_msg(
+                sender_address,
+                contract,
+                &json!({
+                    "transfer": {
+                        "recipient": p.recipient,
+                        "amount": p.amount.to_string(),
+                    }
+                }),
+            )
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct BalanceResponse {
+    balance: String,
+}
+
+/// Queries the CW20 balance of `address` with the contract's `balance` smart query.
+pub async fn cw20_balance(grpc_endpoint: &str, contract: &str, address: &str) -> Result<u128> {
+    let response: BalanceResponse = grpc::smart_query(
+        grpc_endpoint,
+        contract,
+        &json!({ "balance": { "address": address } }),
+    )
+    .await?;
+
+    Ok(response.balance.parse()?)
+}
+
+/// Checks that `holder` owns enough of every CW20 token in `payments` to pay them all.
+pub async fn preflight_cw20_balances(
+    grpc_endpoint: &str,
+    holder: &str,
+    payments: &[Payment],
+) -> Result<()> {
+    let mut totals = BTreeMap::<&str, u128>::new();
+    for p in payments {
+        if let Some(contract) = cw20_contract(&p.denom) {
+            *totals.entry(contract).or_insert(0) += p.amount as u128;
+        }
+    }
+
+    for (contract, total) in totals {
+        let balance = cw20_balance(grpc_endpoint, contract, holder).await?;
+        if balance < total {
+            return Err(eyre!(
+                "{} holds {} of CW20 {} but the airdrop pays out {}",
+                holder,
+                balance,
+                contract,
+                total
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Transfers CW20 payments from `sender`, one transaction per batch of `batch_size` payments.
+/// Balances are checked before anything is broadcast.
+pub async fn execute_cw20_airdrop(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<TxConfirmation>> {
+    let address = sender.address(&chain_context.prefix)?;
+    preflight_cw20_balances(grpc_endpoint, &address, &payments).await?;
+
+    let batches = batch_payments(payments, batch_size)
+        .iter()
+        .map(|batch| transfer_msgs_from_payments(&address, batch))
+        .collect::<Result<Vec<Vec<Any>>>>()?;
+    sign_and_broadcast_batches(
+        sender,
+        batches,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+/// Like [`execute_cw20_airdrop`], but transfers tokens owned by `granter` with each batch wrapped
+/// in an authz `MsgExec` signed by `grantee`.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_delegated_cw20_airdrop(
+    granter: &str,
+    grantee: &AccountInfo,
+    payments: Vec<Payment>,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<TxConfirmation>> {
+    preflight_cw20_balances(grpc_endpoint, granter, &payments).await?;
+
+    let grantee_address = grantee.address(&chain_context.prefix)?;
+    let batches = batch_payments(payments, batch_size)
+        .iter()
+        .map(|batch| {
+            let msg = Authz::Exec {
+                grantee: &grantee_address,
+                msgs: transfer_msgs_from_payments(granter, batch)?,
+            }
+            .into_any()?;
+            Ok(vec![msg])
+        })
+        .collect::<Result<Vec<Vec<Any>>>>()?;
+    sign_and_broadcast_batches(
+        grantee,
+        batches,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    #[test]
+    fn builds_cw20_transfer_msgs() {
+        let sender = "juno1n6j7gnld9yxfyh6tflxhjjmt404zruualvjsgm";
+        let contract = "juno1contract";
+        let payments = vec![Payment {
+            recipient: sender.to_string(),
+            amount: 42,
+            denom: format!("{}{}", CW20_PREFIX, contract),
+        }];
+        let msgs = transfer_msgs_from_payments(sender, &payments).unwrap();
+        let msg = MsgExecuteContract::decode(msgs[0].value.as_slice()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&msg.msg).unwrap();
+
+        assert_eq!(msgs[0].type_url, MSG_EXECUTE_CONTRACT_TYPE_URL);
+        assert_eq!(msg.contract, contract);
+        assert_eq!(
+            body,
+            json!({ "transfer": { "recipient": sender, "amount": "42" } })
+        );
+        assert!(transfer_msgs_from_payments(
+            sender,
+            &[Payment {
+                recipient: sender.to_string(),
+                amount: 1,
+                denom: "ujuno".to_string()
+            }]
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,555 @@
+//! Recurring distribution campaigns run by a long-lived daemon.
+//!
+//! The daemon pays one round per tick of a [`Recurrence`], observed on chain like a
+//! [`Schedule`](crate::schedule::Schedule). Each round's payments come from a [`PaymentSource`].
+//! Rounds are recorded in a JSON state file before anything is broadcast, so a round is never
+//! started twice, and a round that isn't confirmed blocks every later round until an operator
+//! resolves it. A lock file next to the state file keeps two daemons from sharing the state.
+use std::{
+    fs::{self, OpenOptions},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{eyre, Result};
+use ocular::{chain::Context, prelude::AccountInfo, tx::FeeInfo};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::{
+    executor::{ExecutionError, ExecutionReport, Executor},
+    multi_send_batches,
+    payments::{read_payments_toml, Payment},
+    retry::{classify, Failure},
+    schedule::{wait_for_trigger, Trigger},
+    snapshot::{snapshot_delegators, snapshot_denom_holders, SnapshotRule},
+};
+
+/// When rounds start, counted from round zero.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Recurrence {
+    /// Every `interval` blocks from height `start`
+    Blocks { start: u64, interval: u64 },
+    /// Every `interval` seconds of block time from `start`, in seconds since the Unix epoch
+    Seconds { start: u64, interval: u64 },
+}
+
+impl Recurrence {
+    /// When `round` starts. Returns an error if that is past the end of time.
+    pub fn trigger(&self, round: u64) -> Result<Trigger> {
+        let (Recurrence::Blocks { start, interval } | Recurrence::Seconds { start, interval }) =
+            self;
+        let at = round
+            .checked_mul(*interval)
+            .and_then(|offset| offset.checked_add(*start))
+            .ok_or_else(|| eyre!("round {} starts too far in the future", round))?;
+
+        Ok(match self {
+            Recurrence::Blocks { .. } => Trigger::Height(at),
+            Recurrence::Seconds { .. } => Trigger::Time(at),
+        })
+    }
+
+    /// Rejects a zero interval, which would start every round at once.
+    fn validate(&self) -> Result<()> {
+        let (Recurrence::Blocks { interval, .. } | Recurrence::Seconds { interval, .. }) = self;
+        if *interval == 0 {
+            return Err(eyre!("rounds must be at least one block or second apart"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Where each round's payments come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaymentSource {
+    /// Payments TOML files matching `pattern`, where `*` and `?` match within the file name. Each
+    /// round pays the first file, by name, that no earlier round has paid.
+    Files { pattern: String },
+    /// A live snapshot taken when the round starts. Each holder is paid
+    /// `amount * numerator / denominator` of `denom`. `Staked` snapshots cover delegations to
+    /// `validators` and can't include unbonding entries.
+    Snapshot {
+        rule: SnapshotRule,
+        validators: Vec<String>,
+        denom: String,
+        numerator: u128,
+        denominator: u128,
+    },
+}
+
+impl PaymentSource {
+    /// Checks what can be checked without the chain, so a bad source fails before any round
+    fn validate(&self) -> Result<()> {
+        match self {
+            PaymentSource::Snapshot {
+                rule:
+                    SnapshotRule::Staked {
+                        include_unbonding: true,
+                    },
+                ..
+            } => Err(eyre!("live snapshots can't include unbonding delegations")),
+            PaymentSource::Snapshot { denominator: 0, .. } => {
+                Err(eyre!("snapshot payments can't have a denominator of zero"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The hashes of every transaction in `report` that was included or may still be
+fn report_hashes(report: &ExecutionReport) -> Vec<String> {
+    report
+        .confirmations
+        .iter()
+        .map(|c| c.hash.clone())
+        .chain(report.pending.iter().map(|p| p.hash.clone()))
+        .collect()
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters and `?` any one.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Lists the files matching `pattern`, sorted by path.
+fn matching_files(pattern: &str) -> Result<Vec<String>> {
+    let path = Path::new(pattern);
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let file_pattern: Vec<char> = path
+        .file_name()
+        .ok_or_else(|| eyre!("{} has no file name to match", pattern))?
+        .to_string_lossy()
+        .chars()
+        .collect();
+
+    let mut files = Vec::<String>::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name: Vec<char> = entry.file_name().to_string_lossy().chars().collect();
+        if entry.file_type()?.is_file() && glob_match(&file_pattern, &name) {
+            files.push(entry.path().to_string_lossy().to_string());
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// Progress of a round.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundStatus {
+    /// Payments are being broadcast, or the daemon stopped while they were
+    Sending,
+    Confirmed,
+    Failed,
+}
+
+/// The persisted record of one round.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RoundRecord {
+    pub round: u64,
+    pub status: RoundStatus,
+    /// The payments file, or a description of the snapshot the payments came from
+    pub source: String,
+    pub payment_count: usize,
+    #[serde(default)]
+    pub tx_hashes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+/// Every round the daemon has started.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct DaemonState {
+    pub rounds: Vec<RoundRecord>,
+}
+
+impl DaemonState {
+    /// Reads the state at `path`. A missing file is a campaign that hasn't started.
+    pub fn read(path: &str) -> Result<DaemonState> {
+        if !Path::new(path).exists() {
+            return Ok(DaemonState::default());
+        }
+
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the state to `path`, replacing the old file only once the new one is complete.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        Ok(fs::rename(tmp, path)?)
+    }
+
+    /// The number of the next round, failing if the last round isn't confirmed.
+    pub fn next_round(&self) -> Result<u64> {
+        match self.rounds.last() {
+            None => Ok(0),
+            Some(last) if last.status == RoundStatus::Confirmed => Ok(last.round + 1),
+            Some(last) => Err(eyre!(
+                "round {} is {:?}; check its transactions and resolve it before round {} can start",
+                last.round,
+                last.status,
+                last.round + 1
+            )),
+        }
+    }
+
+    /// Records the operator's resolution of a round that didn't confirm on its own.
+    pub fn resolve(&mut self, round: u64, status: RoundStatus) -> Result<()> {
+        let record = self
+            .rounds
+            .iter_mut()
+            .find(|r| r.round == round)
+            .ok_or_else(|| eyre!("round {} has not been started", round))?;
+        record.status = status;
+        record.finished_at = Some(unix_now()?);
+
+        Ok(())
+    }
+
+    /// Whether a round has already paid from `source`
+    fn used(&self, source: &str) -> bool {
+        self.rounds.iter().any(|r| r.source == source)
+    }
+}
+
+fn unix_now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Holds the lock file of a state file for as long as it lives.
+struct StateLock {
+    path: String,
+}
+
+impl StateLock {
+    fn acquire(state_path: &str) -> Result<StateLock> {
+        let path = format!("{}.lock", state_path);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| {
+                eyre!(
+                    "{} is locked by another daemon ({}); remove {} if none is running",
+                    state_path,
+                    e,
+                    path
+                )
+            })?;
+
+        Ok(StateLock { path })
+    }
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// How the daemon runs its campaign.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DaemonConfig {
+    pub recurrence: Recurrence,
+    pub source: PaymentSource,
+    pub batch_size: usize,
+    /// Path of the JSON state file
+    pub state_path: String,
+    /// How often to check the chain while waiting for a round, or for its payments file
+    pub poll_interval: Duration,
+    /// Stop after this many rounds in total, if set
+    pub max_rounds: Option<u64>,
+}
+
+/// Runs a recurring campaign from `sender`.
+pub struct Daemon<'a> {
+    config: DaemonConfig,
+    sender: &'a AccountInfo,
+    fee_info: FeeInfo,
+    chain_context: &'a Context,
+    rpc_endpoint: &'a str,
+    grpc_endpoint: &'a str,
+}
+
+impl<'a> Daemon<'a> {
+    /// Returns an error if the recurrence or payment source can never work, rather than at the
+    /// first round.
+    pub fn new(
+        config: DaemonConfig,
+        sender: &'a AccountInfo,
+        fee_info: FeeInfo,
+        chain_context: &'a Context,
+        rpc_endpoint: &'a str,
+        grpc_endpoint: &'a str,
+    ) -> Result<Self> {
+        config.recurrence.validate()?;
+        config.source.validate()?;
+        Ok(Daemon {
+            config,
+            sender,
+            fee_info,
+            chain_context,
+            rpc_endpoint,
+            grpc_endpoint,
+        })
+    }
+
+    /// The record of every round started so far
+    pub fn status(&self) -> Result<Vec<RoundRecord>> {
+        Ok(DaemonState::read(&self.config.state_path)?.rounds)
+    }
+
+    /// Runs rounds until `max_rounds` is reached, or until a round fails to confirm.
+    pub async fn run(&self) -> Result<()> {
+        let _lock = StateLock::acquire(&self.config.state_path)?;
+        loop {
+            let state = DaemonState::read(&self.config.state_path)?;
+            let round = state.next_round()?;
+            if matches!(self.config.max_rounds, Some(max) if round >= max) {
+                return Ok(());
+            }
+
+            let record = self.run_round(state, round).await?;
+            if record.status != RoundStatus::Confirmed {
+                return Err(eyre!(
+                    "round {} failed: {}",
+                    record.round,
+                    record.error.unwrap_or_default()
+                ));
+            }
+        }
+    }
+
+    /// Waits for `round` to start and for its payments, then pays them.
+    async fn run_round(&self, mut state: DaemonState, round: u64) -> Result<RoundRecord> {
+        let trigger = self.config.recurrence.trigger(round)?;
+        wait_for_trigger(self.rpc_endpoint, &trigger, self.config.poll_interval).await?;
+
+        // Transient query failures are retried at the next poll, like the trigger's
+        let (source, payments) = loop {
+            match self.payments(&state).await {
+                Ok(Some(found)) => break found,
+                Ok(None) => {}
+                Err(e) if classify(&e) == Failure::Safe => {}
+                Err(e) => return Err(e),
+            }
+            sleep(self.config.poll_interval).await;
+        };
+
+        let address = self.sender.address(&self.chain_context.prefix)?;
+        let batches = multi_send_batches(&address, payments.clone(), self.config.batch_size)?;
+        state.rounds.push(RoundRecord {
+            round,
+            status: RoundStatus::Sending,
+            source,
+            payment_count: payments.len(),
+            tx_hashes: Vec::new(),
+            error: None,
+            started_at: unix_now()?,
+            finished_at: None,
+        });
+        state.write(&self.config.state_path)?;
+
+        let batch_count = batches.len();
+        let result = Executor::new(
+            self.sender,
+            self.fee_info.clone(),
+            self.chain_context,
+            self.rpc_endpoint,
+            self.grpc_endpoint,
+        )
+        .execute(batches)
+        .await;
+
+        let record = state
+            .rounds
+            .last_mut()
+            .ok_or_else(|| eyre!("round {} was not recorded", round))?;
+        match result {
+            Ok(report) => {
+                record.tx_hashes = report_hashes(&report);
+                let failed = report.confirmations.iter().find(|c| !c.is_ok());
+                if let Some(c) = failed {
+                    record.status = RoundStatus::Failed;
+                    record.error = Some(format!("transaction {} failed: {}", c.hash, c.log));
+                } else if report.confirmations.len() < batch_count {
+                    record.status = RoundStatus::Failed;
+                    record.error = Some("not every batch was sent".to_string());
+                } else {
+                    record.status = RoundStatus::Confirmed;
+                }
+            }
+            Err(e) => {
+                // Batches may have been included before the executor stopped
+                if let Some(partial) = e.downcast_ref::<ExecutionError>() {
+                    record.tx_hashes = report_hashes(&partial.report);
+                }
+                record.status = RoundStatus::Failed;
+                record.error = Some(format!("{:#}", e));
+            }
+        }
+        record.finished_at = Some(unix_now()?);
+        let record = record.clone();
+        state.write(&self.config.state_path)?;
+
+        Ok(record)
+    }
+
+    /// The payments for the next round and where they came from, or `None` if the source has
+    /// nothing new yet.
+    async fn payments(&self, state: &DaemonState) -> Result<Option<(String, Vec<Payment>)>> {
+        match &self.config.source {
+            PaymentSource::Files { pattern } => {
+                let next = matching_files(pattern)?
+                    .into_iter()
+                    .find(|f| !state.used(f));
+                match next {
+                    Some(file) => {
+                        let payments_toml = read_payments_toml(&file)?;
+                        payments_toml.ensure_no_nft_payments()?;
+                        Ok(Some((file, payments_toml.payments)))
+                    }
+                    None => Ok(None),
+                }
+            }
+            PaymentSource::Snapshot {
+                rule,
+                validators,
+                denom,
+                numerator,
+                denominator,
+            } => {
+                let snapshot = match rule {
+                    SnapshotRule::Balance { denom } => {
+                        snapshot_denom_holders(self.rpc_endpoint, self.grpc_endpoint, denom, None)
+                            .await?
+                    }
+                    SnapshotRule::Staked {
+                        include_unbonding: false,
+                    } => {
+                        let validators: Vec<&str> = validators.iter().map(|v| v.as_str()).collect();
+                        snapshot_delegators(
+                            self.rpc_endpoint,
+                            self.grpc_endpoint,
+                            &validators,
+                            None,
+                        )
+                        .await?
+                    }
+                    SnapshotRule::Staked { .. } => {
+                        return Err(eyre!("live snapshots can't include unbonding delegations"))
+                    }
+                };
+                let source = format!(
+                    "{:?} snapshot at height {}",
+                    rule,
+                    snapshot.height.unwrap_or_default()
+                );
+
+                Ok(Some((
+                    source,
+                    snapshot.to_payments(denom, *numerator, *denominator)?,
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(round: u64, status: RoundStatus, source: &str) -> RoundRecord {
+        RoundRecord {
+            round,
+            status,
+            source: source.to_string(),
+            payment_count: 1,
+            tx_hashes: Vec::new(),
+            error: None,
+            started_at: 0,
+            finished_at: None,
+        }
+    }
+
+    #[test]
+    fn blocks_rounds_after_an_unconfirmed_round() {
+        let mut state = DaemonState::default();
+        assert_eq!(state.next_round().unwrap(), 0);
+
+        state
+            .rounds
+            .push(record(0, RoundStatus::Confirmed, "rewards/week-01.toml"));
+        state
+            .rounds
+            .push(record(1, RoundStatus::Sending, "rewards/week-02.toml"));
+        assert!(state.next_round().is_err());
+        assert!(state.used("rewards/week-02.toml"));
+
+        state.resolve(1, RoundStatus::Confirmed).unwrap();
+        assert_eq!(state.next_round().unwrap(), 2);
+
+        let recurrence = Recurrence::Seconds {
+            start: 1_700_000_000,
+            interval: 604_800,
+        };
+        assert_eq!(recurrence.trigger(2).unwrap(), Trigger::Time(1_701_209_600));
+        assert!(recurrence.trigger(u64::MAX).is_err());
+        let every_block = Recurrence::Blocks {
+            start: 100,
+            interval: 0,
+        };
+        assert!(every_block.validate().is_err());
+    }
+
+    #[test]
+    fn matches_file_name_patterns() {
+        let chars = |s: &str| s.chars().collect::<Vec<char>>();
+
+        assert!(glob_match(&chars("week-*.toml"), &chars("week-01.toml")));
+        assert!(glob_match(&chars("week-??.toml"), &chars("week-01.toml")));
+        assert!(!glob_match(&chars("week-*.toml"), &chars("week-01.json")));
+        assert!(!glob_match(&chars("week-?.toml"), &chars("week-01.toml")));
+    }
+
+    #[test]
+    fn rejects_sources_that_can_never_pay() {
+        let snapshot = |include_unbonding: bool, denominator: u128| PaymentSource::Snapshot {
+            rule: SnapshotRule::Staked { include_unbonding },
+            validators: vec!["cosmosvaloper1sjllsnramtg3ewxqwwrwjxfgc4n4ef9u2lcnj0".to_string()],
+            denom: "uatom".to_string(),
+            numerator: 1,
+            denominator,
+        };
+
+        assert!(snapshot(false, 100).validate().is_ok());
+        assert!(snapshot(true, 100).validate().is_err());
+        assert!(snapshot(false, 0).validate().is_err());
+        assert!(PaymentSource::Files {
+            pattern: "rewards/week-*.toml".to_string()
+        }
+        .validate()
+        .is_ok());
+    }
+}
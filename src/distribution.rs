@@ -0,0 +1,339 @@
+//! Strategies for dividing a fixed pool between the addresses in a [`Snapshot`].
+//!
+//! All allocations are computed with integer arithmetic and rounded with the largest-remainder
+//! method, so the generated payments always sum to exactly the pool.
+use std::collections::BTreeMap;
+
+use eyre::{eyre, Result};
+
+use crate::{
+    math::{isqrt, mul_div_rem},
+    payments::Payment,
+    snapshot::Snapshot,
+};
+
+/// How each address's share of the pool is weighted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Every address with a non-zero holding receives the same amount
+    Flat,
+    /// Proportional to the holding
+    ProRata,
+    /// Proportional to the integer square root of the holding in base units
+    SquareRoot,
+    /// A fixed weight per tier. Each address gets the weight of the highest tier it qualifies
+    /// for; addresses below every tier receive nothing.
+    Tiered(Vec<Tier>),
+}
+
+/// A holding threshold and the weight given to addresses that reach it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tier {
+    pub min_holding: u128,
+    pub weight: u128,
+}
+
+/// A pool to be distributed with a [`Strategy`], optionally bounded per address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Distribution {
+    pub strategy: Strategy,
+    pub pool: u128,
+    /// Maximum any address may receive. Excess is redistributed to the uncapped addresses.
+    pub cap: Option<u128>,
+    /// Minimum every eligible address receives
+    pub floor: Option<u128>,
+}
+
+impl Distribution {
+    pub fn new(strategy: Strategy, pool: u128) -> Self {
+        Distribution {
+            strategy,
+            pool,
+            cap: None,
+            floor: None,
+        }
+    }
+
+    /// Computes the weight of a single holding under the strategy.
+    pub fn weight(&self, holding: u128) -> u128 {
+        if holding == 0 {
+            return 0;
+        }
+
+        match &self.strategy {
+            Strategy::Flat => 1,
+            Strategy::ProRata => holding,
+            Strategy::SquareRoot => isqrt(holding),
+            Strategy::Tiered(tiers) => tiers
+                .iter()
+                .filter(|t| holding >= t.min_holding)
+                .max_by_key(|t| t.min_holding)
+                .map(|t| t.weight)
+                .unwrap_or(0),
+        }
+    }
+
+    /// Allocates the pool between the addresses in `snapshot`. Addresses with zero weight are
+    /// omitted. The allocations sum to exactly [`Distribution::pool`].
+    pub fn allocate(&self, snapshot: &Snapshot) -> Result<BTreeMap<String, u128>> {
+        let eligible: Vec<(&str, u128)> = snapshot
+            .holdings
+            .iter()
+            .map(|h| (h.address.as_str(), self.weight(h.amount)))
+            .filter(|(_, w)| *w > 0)
+            .collect();
+
+        if eligible.is_empty() {
+            return Err(eyre!("no addresses are eligible for the distribution"));
+        }
+
+        if let (Some(cap), Some(floor)) = (self.cap, self.floor) {
+            if cap < floor {
+                return Err(eyre!("cap {} is below floor {}", cap, floor));
+            }
+        }
+
+        let count = eligible.len() as u128;
+        if let Some(floor) = self.floor {
+            if !matches!(floor.checked_mul(count), Some(f) if f <= self.pool) {
+                return Err(eyre!(
+                    "pool of {} can't pay the floor of {} to {} addresses",
+                    self.pool,
+                    floor,
+                    count
+                ));
+            }
+        }
+
+        if let Some(cap) = self.cap {
+            if matches!(cap.checked_mul(count), Some(c) if c < self.pool) {
+                return Err(eyre!(
+                    "pool of {} exceeds the cap of {} for {} addresses",
+                    self.pool,
+                    cap,
+                    count
+                ));
+            }
+        }
+
+        // Pin addresses whose ideal share falls outside [floor, cap] to the bound, then share
+        // what's left among the rest. Each pass pins at least one address, so this terminates.
+        let mut pinned = BTreeMap::<usize, u128>::new();
+        loop {
+            let free: Vec<usize> = (0..eligible.len())
+                .filter(|i| !pinned.contains_key(i))
+                .collect();
+            let remaining = remaining_pool(self.pool, &pinned)?;
+            if free.is_empty() {
+                break;
+            }
+
+            let total_weight: u128 = free.iter().map(|i| eligible[*i].1).sum();
+            let ideal = |i: usize| mul_div_rem(remaining, eligible[i].1, total_weight);
+            let mut changed = false;
+            if let Some(cap) = self.cap {
+                for i in &free {
+                    let (q, r) = ideal(*i);
+                    if q > cap || (q == cap && r > 0) {
+                        pinned.insert(*i, cap);
+                        changed = true;
+                    }
+                }
+            }
+
+            if !changed {
+                if let Some(floor) = self.floor {
+                    for i in &free {
+                        if ideal(*i).0 < floor {
+                            pinned.insert(*i, floor);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        let free: Vec<usize> = (0..eligible.len())
+            .filter(|i| !pinned.contains_key(i))
+            .collect();
+        let remaining = remaining_pool(self.pool, &pinned)?;
+        let mut allocations = BTreeMap::<String, u128>::new();
+        if free.is_empty() {
+            if remaining > 0 {
+                return Err(eyre!("{} of the pool could not be allocated", remaining));
+            }
+        } else {
+            let weights: Vec<u128> = free.iter().map(|i| eligible[*i].1).collect();
+            for (i, amount) in free.iter().zip(split_pool(remaining, &weights)?) {
+                allocations.insert(eligible[*i].0.to_string(), amount);
+            }
+        }
+
+        for (i, amount) in pinned {
+            allocations.insert(eligible[i].0.to_string(), amount);
+        }
+
+        Ok(allocations)
+    }
+
+    /// Allocates the pool and converts the allocations into payments of `denom`.
+    pub fn to_payments(&self, snapshot: &Snapshot, denom: &str) -> Result<Vec<Payment>> {
+        payments_from_allocations(&self.allocate(snapshot)?, denom)
+    }
+}
+
+fn remaining_pool(pool: u128, pinned: &BTreeMap<usize, u128>) -> Result<u128> {
+    pinned
+        .values()
+        .try_fold(pool, |acc, a| acc.checked_sub(*a))
+        .ok_or_else(|| eyre!("per-address bounds exceed the pool of {}", pool))
+}
+
+/// Splits `pool` proportionally to `weights` using the largest-remainder method: every share is
+/// rounded down, then the leftover units go one each to the shares with the largest remainders
+/// (ties go to the earliest index). The result always sums to `pool`.
+pub fn split_pool(pool: u128, weights: &[u128]) -> Result<Vec<u128>> {
+    let total = weights
+        .iter()
+        .try_fold(0u128, |acc, w| acc.checked_add(*w))
+        .ok_or_else(|| eyre!("total weight overflows u128"))?;
+    if total == 0 {
+        if pool == 0 {
+            return Ok(vec![0; weights.len()]);
+        }
+
+        return Err(eyre!(
+            "can't split a pool of {} with zero total weight",
+            pool
+        ));
+    }
+
+    let mut shares = Vec::<u128>::with_capacity(weights.len());
+    let mut remainders = Vec::<(u128, usize)>::with_capacity(weights.len());
+    for (i, w) in weights.iter().enumerate() {
+        let (q, r) = mul_div_rem(pool, *w, total);
+        shares.push(q);
+        remainders.push((r, i));
+    }
+
+    let leftover = pool - shares.iter().sum::<u128>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, i) in remainders.iter().take(leftover as usize) {
+        shares[*i] += 1;
+    }
+
+    Ok(shares)
+}
+
+/// Converts an address -> amount map into payments of `denom`, skipping zero amounts.
+pub fn payments_from_allocations(
+    allocations: &BTreeMap<String, u128>,
+    denom: &str,
+) -> Result<Vec<Payment>> {
+    allocations
+        .iter()
+        .filter(|(_, amount)| **amount > 0)
+        .map(|(address, amount)| {
+            Ok(Payment {
+                recipient: address.clone(),
+                amount: u64::try_from(*amount)
+                    .map_err(|_| eyre!("payment to {} exceeds u64::MAX", address))?,
+                denom: denom.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(amounts: &[u128]) -> Snapshot {
+        Snapshot::from_holdings(
+            None,
+            None,
+            amounts
+                .iter()
+                .enumerate()
+                .map(|(i, a)| (format!("addr{}", i), *a))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn largest_remainder_sums_to_pool() {
+        assert_eq!(split_pool(10, &[1, 1, 1]).unwrap(), vec![4, 3, 3]);
+        assert_eq!(
+            split_pool(100, &[1, 2, 3, 4]).unwrap(),
+            vec![10, 20, 30, 40]
+        );
+        assert_eq!(split_pool(7, &[5, 3, 2]).unwrap(), vec![4, 2, 1]);
+        assert!(split_pool(1, &[0, 0]).is_err());
+    }
+
+    #[test]
+    fn allocates_with_each_strategy() {
+        let snapshot = snapshot(&[1, 4, 9, 100]);
+        let pool = 1000;
+        let flat = Distribution::new(Strategy::Flat, pool)
+            .allocate(&snapshot)
+            .unwrap();
+        let pro_rata = Distribution::new(Strategy::ProRata, pool)
+            .allocate(&snapshot)
+            .unwrap();
+        let sqrt = Distribution::new(Strategy::SquareRoot, pool)
+            .allocate(&snapshot)
+            .unwrap();
+        let tiered = Distribution::new(
+            Strategy::Tiered(vec![
+                Tier {
+                    min_holding: 4,
+                    weight: 1,
+                },
+                Tier {
+                    min_holding: 100,
+                    weight: 2,
+                },
+            ]),
+            pool,
+        )
+        .allocate(&snapshot)
+        .unwrap();
+
+        assert_eq!(flat.values().copied().collect::<Vec<_>>(), vec![250; 4]);
+        assert_eq!(
+            pro_rata.values().copied().collect::<Vec<_>>(),
+            vec![9, 35, 79, 877]
+        );
+        assert_eq!(
+            sqrt.values().copied().collect::<Vec<_>>(),
+            vec![63, 125, 187, 625]
+        );
+        assert_eq!(tiered.len(), 3);
+        assert_eq!(tiered["addr3"], 500);
+        for allocation in [flat, pro_rata, sqrt, tiered] {
+            assert_eq!(allocation.values().sum::<u128>(), pool);
+        }
+    }
+
+    #[test]
+    fn redistributes_capped_excess_and_applies_floor() {
+        let snapshot = snapshot(&[1, 4, 9, 100]);
+        let mut distribution = Distribution::new(Strategy::ProRata, 1000);
+        distribution.cap = Some(400);
+        distribution.floor = Some(50);
+        let allocations = distribution.allocate(&snapshot).unwrap();
+
+        assert_eq!(allocations["addr3"], 400);
+        assert_eq!(allocations["addr0"], 50);
+        assert_eq!(allocations.values().sum::<u128>(), 1000);
+        assert!(allocations.values().all(|a| *a >= 50 && *a <= 400));
+
+        distribution.cap = Some(200);
+        assert!(distribution.allocate(&snapshot).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Minimum payout rules for allocations too small to be worth their gas.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{filters::redistribute_amount, payments::Payment};
+
+/// What happens to a payment below the threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DustPolicy {
+    /// Drop the payment and split its amount between the remaining payments of the same denom
+    Redistribute,
+    /// Raise the payment to the threshold
+    RoundUp,
+    /// Drop the payment and record it in a carry-over ledger, to be added to the same recipient
+    /// in the next campaign
+    CarryOver,
+}
+
+/// Payments of less than `threshold` base units are handled according to `policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DustRule {
+    pub threshold: u64,
+    pub policy: DustPolicy,
+}
+
+/// The decision taken for a single sub-threshold payment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DustDecision {
+    pub recipient: String,
+    pub denom: String,
+    /// Amount before the rule was applied
+    pub amount: u64,
+    pub policy: DustPolicy,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DustReport {
+    pub threshold: u64,
+    pub decisions: Vec<DustDecision>,
+    /// Amount redistributed to other payments, per denom
+    pub redistributed: BTreeMap<String, u128>,
+    /// Amount added by rounding up, per denom
+    pub rounded_up: BTreeMap<String, u128>,
+    /// Amount moved to the carry-over ledger, per denom
+    pub carried_over: BTreeMap<String, u128>,
+    /// Amount taken from the previous carry-over ledger, per denom
+    pub carried_in: BTreeMap<String, u128>,
+}
+
+/// Payments after a [`DustRule`] has been applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DustOutcome {
+    pub payments: Vec<Payment>,
+    /// Entries for the next campaign's carry-over ledger
+    pub carry_over: Vec<Payment>,
+    pub report: DustReport,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+struct CarryOverLedger {
+    carry_over: Vec<Payment>,
+}
+
+/// Reads a carry-over ledger. A missing file is treated as an empty ledger.
+pub fn read_carry_over_ledger(path: &str) -> Result<Vec<Payment>> {
+    if !Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+
+    let toml_string = fs::read_to_string(path)?;
+    let ledger: CarryOverLedger = toml::from_str(toml_string.as_str())?;
+
+    Ok(ledger.carry_over)
+}
+
+/// Serializes carry-over entries into a ledger at the specified path
+pub fn write_carry_over_ledger(path: &str, carry_over: &[Payment]) -> Result<()> {
+    let ledger = CarryOverLedger {
+        carry_over: carry_over.to_vec(),
+    };
+    let toml_string = toml::to_string(&ledger)?;
+    Ok(fs::write(path, toml_string)?)
+}
+
+impl DustRule {
+    /// Applies the rule to `payments`. Entries from a previous carry-over ledger in `carried_in`
+    /// are first added to the recipient's payment of the same denom (or paid on their own if the
+    /// recipient has none), so a carried amount is paid once it reaches the threshold.
+    pub fn apply(&self, payments: Vec<Payment>, carried_in: Vec<Payment>) -> Result<DustOutcome> {
+        let mut report = DustReport {
+            threshold: self.threshold,
+            ..Default::default()
+        };
+        let payments = merge_carry_over(payments, carried_in, &mut report)?;
+
+        let mut kept = Vec::<Payment>::with_capacity(payments.len());
+        let mut carry_over = Vec::<Payment>::new();
+        let mut dropped = BTreeMap::<String, u128>::new();
+        for mut p in payments {
+            if p.amount >= self.threshold {
+                kept.push(p);
+                continue;
+            }
+
+            report.decisions.push(DustDecision {
+                recipient: p.recipient.clone(),
+                denom: p.denom.clone(),
+                amount: p.amount,
+                policy: self.policy,
+            });
+            match self.policy {
+                DustPolicy::Redistribute => {
+                    *dropped.entry(p.denom).or_insert(0) += p.amount as u128;
+                }
+                DustPolicy::RoundUp => {
+                    *report.rounded_up.entry(p.denom.clone()).or_insert(0) +=
+                        (self.threshold - p.amount) as u128;
+                    p.amount = self.threshold;
+                    kept.push(p);
+                }
+                DustPolicy::CarryOver => {
+                    *report.carried_over.entry(p.denom.clone()).or_insert(0) += p.amount as u128;
+                    carry_over.push(p);
+                }
+            }
+        }
+
+        for (denom, amount) in dropped {
+            if !kept.iter().any(|p| p.denom == denom) {
+                return Err(eyre!(
+                    "every {} payment is below the threshold of {}; nothing to redistribute to",
+                    denom,
+                    self.threshold
+                ));
+            }
+
+            redistribute_amount(&mut kept, &denom, amount)?;
+            report.redistributed.insert(denom, amount);
+        }
+
+        Ok(DustOutcome {
+            payments: kept,
+            carry_over,
+            report,
+        })
+    }
+
+    /// Applies the rule with the carry-over ledger at `ledger_path` as input. The ledger is not
+    /// modified; write [`DustOutcome::carry_over`] back with [`write_carry_over_ledger`] once the
+    /// airdrop has been confirmed, so a failed run doesn't lose carried amounts.
+    pub fn apply_with_ledger(
+        &self,
+        payments: Vec<Payment>,
+        ledger_path: &str,
+    ) -> Result<DustOutcome> {
+        self.apply(payments, read_carry_over_ledger(ledger_path)?)
+    }
+}
+
+fn merge_carry_over(
+    mut payments: Vec<Payment>,
+    carried_in: Vec<Payment>,
+    report: &mut DustReport,
+) -> Result<Vec<Payment>> {
+    for c in carried_in {
+        *report.carried_in.entry(c.denom.clone()).or_insert(0) += c.amount as u128;
+        match payments
+            .iter_mut()
+            .find(|p| p.recipient == c.recipient && p.denom == c.denom)
+        {
+            Some(p) => {
+                p.amount = p
+                    .amount
+                    .checked_add(c.amount)
+                    .ok_or_else(|| eyre!("payment to {} exceeds u64::MAX", p.recipient))?;
+            }
+            None => payments.push(c),
+        }
+    }
+
+    Ok(payments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(recipient: &str, amount: u64) -> Payment {
+        Payment {
+            recipient: recipient.to_string(),
+            amount,
+            denom: "uatom".to_string(),
+        }
+    }
+
+    fn payments() -> Vec<Payment> {
+        vec![
+            payment("a", 1000),
+            payment("b", 3000),
+            payment("c", 40),
+            payment("d", 60),
+        ]
+    }
+
+    #[test]
+    fn redistributes_or_rounds_up_dust() {
+        let redistributed = DustRule {
+            threshold: 100,
+            policy: DustPolicy::Redistribute,
+        }
+        .apply(payments(), vec![])
+        .unwrap();
+        let rounded = DustRule {
+            threshold: 100,
+            policy: DustPolicy::RoundUp,
+        }
+        .apply(payments(), vec![])
+        .unwrap();
+
+        assert_eq!(
+            redistributed.payments,
+            vec![payment("a", 1025), payment("b", 3075)]
+        );
+        assert_eq!(redistributed.report.decisions.len(), 2);
+        assert_eq!(redistributed.report.redistributed["uatom"], 100);
+        assert_eq!(
+            rounded.payments[2..],
+            [payment("c", 100), payment("d", 100)]
+        );
+        assert_eq!(rounded.report.rounded_up["uatom"], 100);
+    }
+
+    #[test]
+    fn carries_dust_over_to_next_campaign() {
+        let rule = DustRule {
+            threshold: 100,
+            policy: DustPolicy::CarryOver,
+        };
+        let first = rule.apply(payments(), vec![]).unwrap();
+
+        assert_eq!(first.payments.len(), 2);
+        assert_eq!(first.carry_over, vec![payment("c", 40), payment("d", 60)]);
+
+        let path = std::env::temp_dir()
+            .join("carry_over_ledger_test.toml")
+            .into_os_string()
+            .into_string()
+            .unwrap();
+        write_carry_over_ledger(&path, &first.carry_over).unwrap();
+        let second = rule
+            .apply_with_ledger(vec![payment("c", 70), payment("e", 500)], &path)
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(second.payments, vec![payment("c", 110), payment("e", 500)]);
+        assert_eq!(second.carry_over, vec![payment("d", 60)]);
+        assert_eq!(second.report.carried_in["uatom"], 100);
+    }
+}
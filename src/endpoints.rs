@@ -0,0 +1,159 @@
+//! Ordered lists of nodes to fail over between.
+//!
+//! Before use, every node is health checked: it must report the expected chain id, must not be
+//! catching up, and must be within `max_lag_blocks` of the highest node in the list. The healthy
+//! nodes are used in the order they were given, moving on to the next one when a node fails.
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use futures::future::join_all;
+use tokio::time::timeout;
+
+use crate::{
+    grpc::{self, NodeStatus},
+    preflight::check_status,
+};
+
+/// The RPC and gRPC addresses of one node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub rpc: String,
+    pub grpc: String,
+}
+
+impl Endpoint {
+    pub fn new(rpc: &str, grpc: &str) -> Self {
+        Endpoint {
+            rpc: rpc.to_string(),
+            grpc: grpc.to_string(),
+        }
+    }
+}
+
+/// Nodes in order of preference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoints {
+    pub nodes: Vec<Endpoint>,
+    /// How many blocks a node may trail the highest node and still be used
+    pub max_lag_blocks: u64,
+    /// Number of healthy nodes each transaction is broadcast to at once. The first is the one
+    /// whose answer is used; the others only speed up propagation.
+    pub broadcast_fanout: usize,
+    /// How long a node may take to answer its health checks before it's counted as unreachable
+    pub health_check_timeout: Duration,
+}
+
+impl Endpoints {
+    pub fn new(nodes: Vec<Endpoint>) -> Self {
+        Endpoints {
+            nodes,
+            max_lag_blocks: 5,
+            broadcast_fanout: 1,
+            health_check_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// A single node with no failover
+    pub fn single(rpc_endpoint: &str, grpc_endpoint: &str) -> Self {
+        Endpoints::new(vec![Endpoint::new(rpc_endpoint, grpc_endpoint)])
+    }
+
+    /// Checks every node and returns the healthy ones in order of preference. Returns an error
+    /// listing what was wrong with each node if none are healthy.
+    pub async fn healthy(&self, chain_id: &str) -> Result<Vec<Endpoint>> {
+        let checks = self.nodes.iter().map(|node| async move {
+            let check = async {
+                let status = grpc::node_status(&node.rpc).await?;
+                grpc::connect(&node.grpc).await?;
+                Ok(status)
+            };
+            match timeout(self.health_check_timeout, check).await {
+                Ok(result) => result,
+                Err(_) => Err(eyre!("no answer within {:?}", self.health_check_timeout)),
+            }
+        });
+        let statuses = join_all(checks).await;
+
+        select_healthy(&self.nodes, statuses, chain_id, self.max_lag_blocks)
+    }
+}
+
+/// Picks the nodes whose status passes the health checks, keeping their order.
+fn select_healthy(
+    nodes: &[Endpoint],
+    statuses: Vec<Result<NodeStatus>>,
+    chain_id: &str,
+    max_lag_blocks: u64,
+) -> Result<Vec<Endpoint>> {
+    let best_height = statuses
+        .iter()
+        .flatten()
+        .filter(|s| s.chain_id == chain_id)
+        .map(|s| s.latest_height)
+        .max()
+        .unwrap_or(0);
+
+    let mut healthy = Vec::<Endpoint>::new();
+    let mut problems = Vec::<String>::new();
+    for (node, status) in nodes.iter().zip(statuses) {
+        let problem = match status {
+            Err(e) => format!("unreachable: {}", e),
+            Ok(s) => match check_status(&s, chain_id) {
+                Err(e) => e.to_string(),
+                Ok(()) if s.latest_height + max_lag_blocks < best_height => format!(
+                    "at height {}, {} blocks behind",
+                    s.latest_height,
+                    best_height - s.latest_height
+                ),
+                Ok(()) => {
+                    healthy.push(node.clone());
+                    continue;
+                }
+            },
+        };
+        problems.push(format!("{}: {}", node.rpc, problem));
+    }
+
+    if healthy.is_empty() {
+        return Err(eyre!("no healthy nodes: {}", problems.join("; ")));
+    }
+
+    Ok(healthy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(chain_id: &str, latest_height: u64, catching_up: bool) -> Result<NodeStatus> {
+        Ok(NodeStatus {
+            chain_id: chain_id.to_string(),
+            latest_height,
+            catching_up,
+            latest_block_time: 0,
+        })
+    }
+
+    #[test]
+    fn skips_unhealthy_nodes_in_order() {
+        let nodes: Vec<Endpoint> = (0..5)
+            .map(|i| Endpoint::new(&format!("http://rpc{}", i), &format!("http://grpc{}", i)))
+            .collect();
+        let statuses = vec![
+            Err(eyre!("connection refused")),
+            status("cosmoshub-4", 1_000, false),
+            status("theta-testnet-001", 2_000, false),
+            status("cosmoshub-4", 1_003, true),
+            status("cosmoshub-4", 990, false),
+        ];
+        let healthy = select_healthy(&nodes, statuses, "cosmoshub-4", 5).unwrap();
+
+        assert_eq!(healthy, vec![nodes[1].clone()]);
+
+        let error = select_healthy(&nodes[..1], vec![Err(eyre!("refused"))], "cosmoshub-4", 5);
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("http://rpc0: unreachable"));
+    }
+}
@@ -0,0 +1,596 @@
+//! Pipelined execution of many transactions from one signer.
+//!
+//! Rather than waiting for every batch to commit before sending the next, the executor signs
+//! consecutive batches with locally incremented sequence numbers and keeps up to
+//! `max_in_flight` of them in the mempool at once. If the node reports an account sequence
+//! mismatch, or a transaction drops out of the mempool, the executor re-queries the account's
+//! sequence and re-signs only the batches that were not included. With a [`FeeBump`]
+//! configured, batches rejected as underpriced are re-signed with a higher fee, up to the bump's
+//! ceiling.
+//!
+//! If the executor has to stop after broadcasting, it stops signing new batches, waits for the
+//! ones in flight and returns an [`ExecutionError`] carrying the report of what was included, so
+//! the caller can tell which batches paid out, and what fees were raised, before retrying.
+//!
+//! Given several [`Endpoints`], the executor only uses the nodes that pass their health checks,
+//! and moves on to the next one when a node stops responding.
+use std::{collections::VecDeque, fmt};
+
+use eyre::{eyre, Report, Result};
+use futures::future::join_all;
+use ocular::{
+    chain::Context,
+    cosmrs::{Any, Coin},
+    prelude::AccountInfo,
+    tx::FeeInfo,
+};
+
+use crate::{
+    broadcast::{
+        account_sequence, broadcast_sync, broadcast_with_retry, is_check_tx_rejection, sign_tx,
+        tx_hash, wait_for_tx, ConfirmationOptions, TxConfirmation,
+    },
+    endpoints::{Endpoint, Endpoints},
+    fees::{is_fee_rejection, FeeBump, FeeChange},
+    grpc,
+    preflight::check_chain_prefix,
+    retry::{classify, Failure, RetryPolicy},
+};
+
+/// Tuning for the [`Executor`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecutorOptions {
+    /// Maximum number of broadcast transactions awaiting inclusion at any time
+    pub max_in_flight: usize,
+    /// How many times the executor may re-query its sequence before giving up
+    pub max_resyncs: usize,
+    pub confirmation: ConfirmationOptions,
+    /// Applied to every broadcast and sequence query
+    pub retry: RetryPolicy,
+    /// Raise the fee of batches rejected as underpriced. Off by default.
+    pub fee_bump: Option<FeeBump>,
+    /// Height after which the chain rejects the executor's transactions
+    pub timeout_height: Option<u64>,
+}
+
+impl Default for ExecutorOptions {
+    fn default() -> Self {
+        ExecutorOptions {
+            max_in_flight: 5,
+            max_resyncs: 3,
+            confirmation: ConfirmationOptions::default(),
+            retry: RetryPolicy::default(),
+            fee_bump: None,
+            timeout_height: None,
+        }
+    }
+}
+
+/// A broadcast transaction awaiting inclusion
+struct InFlight {
+    index: usize,
+    sequence: u64,
+    hash: String,
+    fee: Coin,
+    /// Kept to resend the transaction to the next node after a failover
+    tx_bytes: Vec<u8>,
+}
+
+/// The healthy nodes and the one currently in use
+struct Failover {
+    nodes: Vec<Endpoint>,
+    current: usize,
+}
+
+impl Failover {
+    fn node(&self) -> &Endpoint {
+        &self.nodes[self.current]
+    }
+
+    /// Moves to the next node, if there is one
+    fn advance(&mut self) -> bool {
+        if self.current + 1 < self.nodes.len() {
+            self.current += 1;
+            return true;
+        }
+
+        false
+    }
+}
+
+/// A broadcast transaction whose inclusion couldn't be confirmed. It may still land.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingTx {
+    pub batch: usize,
+    pub hash: String,
+}
+
+/// The outcome of [`Executor::execute`].
+#[derive(Clone, Debug, Default)]
+pub struct ExecutionReport {
+    /// Confirmations of the batches included in a block, in batch order
+    pub confirmations: Vec<TxConfirmation>,
+    /// The index of the batch each confirmation belongs to
+    pub batches: Vec<usize>,
+    /// The fee each confirmed transaction paid, matching `confirmations`
+    pub fees: Vec<Coin>,
+    /// Every fee raise, in the order they happened
+    pub fee_changes: Vec<FeeChange>,
+    /// Transactions that were broadcast but not seen in a block before execution stopped
+    pub pending: Vec<PendingTx>,
+    /// Batches that weren't included because the timeout height passed first. Nothing in them
+    /// was paid.
+    pub expired: Vec<usize>,
+    /// Preflight checks that were skipped, and why
+    pub warnings: Vec<String>,
+}
+
+impl ExecutionReport {
+    /// The confirmation of batch `batch`, if it was included in a block
+    pub fn confirmation(&self, batch: usize) -> Option<&TxConfirmation> {
+        self.batches
+            .iter()
+            .position(|b| *b == batch)
+            .map(|i| &self.confirmations[i])
+    }
+
+    /// Returns the report, or an [`ExecutionError`] carrying it if an included transaction
+    /// failed.
+    pub fn into_result(self) -> Result<ExecutionReport> {
+        let failed = self
+            .confirmations
+            .iter()
+            .find(|c| !c.is_ok())
+            .map(|c| c.clone().into_result());
+        match failed {
+            Some(Err(error)) => Err(ExecutionError {
+                report: self,
+                error,
+            }
+            .into()),
+            _ => Ok(self),
+        }
+    }
+}
+
+/// Returned when execution stops after transactions were broadcast, along with the report of
+/// what was included and what may still be pending. Recover it from an [`eyre::Report`] with
+/// `downcast_ref::<ExecutionError>()`.
+#[derive(Debug)]
+pub struct ExecutionError {
+    pub report: ExecutionReport,
+    pub error: Report,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#} ({} batches included, {} possibly pending)",
+            self.error,
+            self.report.confirmations.len(),
+            self.report.pending.len()
+        )
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// Signs and broadcasts batches of messages from a single account.
+pub struct Executor<'a> {
+    signer: &'a AccountInfo,
+    fee_info: FeeInfo,
+    chain_context: &'a Context,
+    endpoints: Endpoints,
+    options: ExecutorOptions,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new(
+        signer: &'a AccountInfo,
+        fee_info: FeeInfo,
+        chain_context: &'a Context,
+        rpc_endpoint: &str,
+        grpc_endpoint: &str,
+    ) -> Self {
+        Executor::with_endpoints(
+            signer,
+            fee_info,
+            chain_context,
+            Endpoints::single(rpc_endpoint, grpc_endpoint),
+        )
+    }
+
+    /// An executor that fails over between `endpoints` instead of using a single node.
+    pub fn with_endpoints(
+        signer: &'a AccountInfo,
+        fee_info: FeeInfo,
+        chain_context: &'a Context,
+        endpoints: Endpoints,
+    ) -> Self {
+        Executor {
+            signer,
+            fee_info,
+            chain_context,
+            endpoints,
+            options: ExecutorOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: ExecutorOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sends each batch as one transaction, in order, and reports the batches that were
+    /// included in a block along with the fees they paid.
+    ///
+    /// A transaction that is included but fails still consumes its sequence, so it is not
+    /// retried. No new batches are sent after such a failure; the batches already in flight are
+    /// waited for, and the report stops at the batches that were included.
+    ///
+    /// With a timeout height set, the first batch rejected or dropped because the height passed
+    /// stops signing. That isn't an error: the batches that didn't make it are listed as expired.
+    ///
+    /// Errors before anything is broadcast are returned as they are. Later errors also stop new
+    /// batches from being sent, and once the batches in flight have been waited for, they are
+    /// returned as an [`ExecutionError`] with the report so far.
+    pub async fn execute(&self, batches: Vec<Vec<Any>>) -> Result<ExecutionReport> {
+        let address = self.signer.address(&self.chain_context.prefix)?;
+        let mut failover = Failover {
+            nodes: self.endpoints.healthy(&self.chain_context.id).await?,
+            current: 0,
+        };
+        let warnings = check_chain_prefix(self.chain_context, &failover.node().grpc)
+            .await?
+            .into_iter()
+            .collect();
+        let (account_number, mut sequence) = self.sequence(&mut failover, &address).await?;
+        let mut fee_info = self.fee_info.clone();
+        let mut fee_changes = Vec::<FeeChange>::new();
+        let mut confirmations = Vec::<Option<(TxConfirmation, Coin)>>::new();
+        confirmations.resize(batches.len(), None);
+        let mut in_flight = VecDeque::<InFlight>::new();
+        let mut pending = Vec::<PendingTx>::new();
+        let mut next = 0;
+        let mut resync_needed = false;
+        let mut resyncs = 0;
+        let mut stopped = false;
+        // Past the timeout height, nothing that isn't included yet ever will be
+        let mut expired = false;
+        // Once set, nothing more is signed and the batches in flight are drained
+        let mut failure: Option<Report> = None;
+
+        loop {
+            while failure.is_none()
+                && !expired
+                && !stopped
+                && !resync_needed
+                && next < batches.len()
+                && in_flight.len() < self.options.max_in_flight.max(1)
+            {
+                let tx_bytes = match sign_tx(
+                    self.signer,
+                    batches[next].clone(),
+                    &fee_info,
+                    &self.chain_context.id,
+                    account_number,
+                    sequence,
+                    self.options.timeout_height,
+                ) {
+                    Ok(tx_bytes) => tx_bytes,
+                    Err(e) => {
+                        failure = Some(e.wrap_err(format!("failed to sign batch {}", next)));
+                        break;
+                    }
+                };
+                match self.broadcast(&mut failover, &in_flight, &tx_bytes).await {
+                    Ok(hash) => {
+                        in_flight.push_back(InFlight {
+                            index: next,
+                            sequence,
+                            hash,
+                            fee: fee_info.get_fee(),
+                            tx_bytes,
+                        });
+                        next += 1;
+                        sequence += 1;
+                    }
+                    // Let the earlier transactions land before trusting the chain's sequence
+                    Err(e) if is_sequence_mismatch(&e) => resync_needed = true,
+                    Err(e) if is_timeout_height(&e) => expired = true,
+                    // Later batches are signed with the raised fee too
+                    Err(e) if self.options.fee_bump.is_some() && is_fee_rejection(&e) => {
+                        let bumped = self
+                            .options
+                            .fee_bump
+                            .as_ref()
+                            .and_then(|b| b.bump(&fee_info));
+                        match bumped {
+                            Some(bumped) => {
+                                fee_changes.push(FeeChange {
+                                    batch: next,
+                                    sequence,
+                                    previous: fee_info.get_fee(),
+                                    new: bumped.get_fee(),
+                                    reason: e.to_string(),
+                                });
+                                fee_info = bumped;
+                            }
+                            None => {
+                                if !is_check_tx_rejection(&e) {
+                                    pending.push(PendingTx {
+                                        batch: next,
+                                        hash: tx_hash(&tx_bytes),
+                                    });
+                                }
+                                failure = Some(e.wrap_err(format!(
+                                    "batch {} was rejected and the fee is already at its ceiling",
+                                    next
+                                )))
+                            }
+                        }
+                    }
+                    // Unless CheckTx turned it down, the transaction may be in a mempool
+                    Err(e) => {
+                        if !is_check_tx_rejection(&e) {
+                            pending.push(PendingTx {
+                                batch: next,
+                                hash: tx_hash(&tx_bytes),
+                            });
+                        }
+                        failure = Some(e.wrap_err(format!("batch {} was rejected", next)));
+                    }
+                }
+            }
+
+            if let Some(oldest) = in_flight.pop_front() {
+                let rpc_endpoint = &failover.node().rpc;
+                match wait_for_tx(rpc_endpoint, &oldest.hash, &self.options.confirmation).await {
+                    Ok(confirmation) => {
+                        stopped |= !confirmation.is_ok();
+                        confirmations[oldest.index] = Some((confirmation, oldest.fee));
+                    }
+                    // The node may have gone down rather than the transaction being dropped
+                    Err(_)
+                        if grpc::node_status(rpc_endpoint).await.is_err() && failover.advance() =>
+                    {
+                        in_flight.push_front(oldest);
+                        self.rebroadcast(&failover, &in_flight).await;
+                    }
+                    // The wait may have failed for another reason after it landed in time
+                    Err(_) if expired => {
+                        let dropped = matches!(
+                            self.sequence(&mut failover, &address).await,
+                            Ok((_, chain_sequence)) if chain_sequence <= oldest.sequence
+                        );
+                        if !dropped {
+                            pending.push(PendingTx {
+                                batch: oldest.index,
+                                hash: oldest.hash,
+                            });
+                        }
+                    }
+                    // Already stopping, so there's nothing to re-sign
+                    Err(_) if failure.is_some() => pending.push(PendingTx {
+                        batch: oldest.index,
+                        hash: oldest.hash,
+                    }),
+                    Err(e) => {
+                        // A transaction that never landed leaves a gap in the sequence, so
+                        // nothing signed after it can land either. Re-sign from it onwards, but
+                        // only if the chain confirms it wasn't included.
+                        match self.sequence(&mut failover, &address).await {
+                            Ok((_, chain_sequence)) if chain_sequence <= oldest.sequence => {
+                                in_flight.clear();
+                                if self.past_timeout_height(&failover).await {
+                                    expired = true;
+                                } else {
+                                    next = oldest.index;
+                                    resync_needed = true;
+                                }
+                            }
+                            Ok(_) => {
+                                failure = Some(e.wrap_err(format!(
+                                    "transaction {} for batch {} may have been included; check \
+                                     it before retrying",
+                                    oldest.hash, oldest.index
+                                )));
+                                pending.push(PendingTx {
+                                    batch: oldest.index,
+                                    hash: oldest.hash,
+                                });
+                            }
+                            Err(e) => {
+                                failure = Some(e);
+                                pending.push(PendingTx {
+                                    batch: oldest.index,
+                                    hash: oldest.hash,
+                                });
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
+
+            if failure.is_some() || expired {
+                break;
+            }
+
+            if resync_needed {
+                resyncs += 1;
+                if resyncs > self.options.max_resyncs {
+                    failure = Some(eyre!(
+                        "account sequence still out of sync after {} attempts",
+                        self.options.max_resyncs
+                    ));
+                    continue;
+                }
+
+                match self.sequence(&mut failover, &address).await {
+                    Ok((_, chain_sequence)) => sequence = chain_sequence,
+                    Err(e) => failure = Some(e),
+                }
+                resync_needed = false;
+                continue;
+            }
+
+            if stopped || next >= batches.len() {
+                break;
+            }
+        }
+
+        let mut report = ExecutionReport {
+            fee_changes,
+            pending,
+            warnings,
+            ..Default::default()
+        };
+        for (batch, confirmation) in confirmations.into_iter().enumerate() {
+            match confirmation {
+                Some((confirmation, fee)) => {
+                    report.confirmations.push(confirmation);
+                    report.batches.push(batch);
+                    report.fees.push(fee);
+                }
+                None if expired && !report.pending.iter().any(|p| p.batch == batch) => {
+                    report.expired.push(batch)
+                }
+                None => {}
+            }
+        }
+
+        match failure {
+            Some(error) => Err(ExecutionError { report, error }.into()),
+            None => Ok(report),
+        }
+    }
+
+    /// Fetches the signer's account number and sequence, failing over between nodes.
+    async fn sequence(&self, failover: &mut Failover, address: &str) -> Result<(u64, u64)> {
+        loop {
+            let grpc_endpoint = &failover.node().grpc;
+            match account_sequence(grpc_endpoint, address, &self.options.retry).await {
+                Err(e) if classify(&e) == Failure::Safe && failover.advance() => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Broadcasts a signed transaction to the current node and, for faster propagation, to the
+    /// next `broadcast_fanout - 1` nodes. Fails over to the next node if the current one can't
+    /// be reached, resending the transactions in flight to it first; the transaction is checked
+    /// for by hash before it is sent again.
+    async fn broadcast(
+        &self,
+        failover: &mut Failover,
+        in_flight: &VecDeque<InFlight>,
+        tx_bytes: &[u8],
+    ) -> Result<String> {
+        loop {
+            let extra = self
+                .endpoints
+                .broadcast_fanout
+                .saturating_sub(1)
+                .min(failover.nodes.len() - 1);
+            let others = (1..=extra).map(|i| {
+                let node = &failover.nodes[(failover.current + i) % failover.nodes.len()];
+                broadcast_sync(&node.rpc, tx_bytes.to_vec())
+            });
+            let primary =
+                broadcast_with_retry(&failover.node().rpc, tx_bytes.to_vec(), &self.options.retry);
+            let (result, _) = futures::join!(primary, join_all(others));
+
+            match result {
+                Err(e) if classify(&e) == Failure::Safe && failover.advance() => {
+                    self.rebroadcast(failover, in_flight).await
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Whether the current node is past the executor's timeout height, if it has one
+    async fn past_timeout_height(&self, failover: &Failover) -> bool {
+        match self.options.timeout_height {
+            Some(timeout_height) => matches!(
+                grpc::node_status(&failover.node().rpc).await,
+                Ok(status) if status.latest_height > timeout_height
+            ),
+            None => false,
+        }
+    }
+
+    /// Resends the transactions in flight, in sequence order, to the current node, which may not
+    /// have seen them before a failover. Rejections are ignored: a transaction the new node
+    /// already holds or has included is found when it's waited for, and one that really was
+    /// dropped is re-signed then.
+    async fn rebroadcast(&self, failover: &Failover, in_flight: &VecDeque<InFlight>) {
+        for tx in in_flight {
+            let _ = broadcast_sync(&failover.node().rpc, tx.tx_bytes.clone()).await;
+        }
+    }
+}
+
+/// Whether an error reports that a transaction was rejected because its timeout height passed
+pub fn is_timeout_height(error: &Report) -> bool {
+    format!("{:#}", error).contains("tx timeout height")
+}
+
+/// Whether an error reports that a transaction was signed with the wrong sequence
+pub fn is_sequence_mismatch(error: &Report) -> bool {
+    let message = error.to_string();
+    message.contains("account sequence mismatch") || message.contains("incorrect account sequence")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_sequence_mismatches() {
+        let mismatch = eyre!(
+            "transaction ABCD was rejected with code 32: account sequence mismatch, expected 5, \
+             got 7: incorrect account sequence"
+        );
+
+        assert!(is_sequence_mismatch(&mismatch));
+        assert!(!is_sequence_mismatch(&eyre!("insufficient fees")));
+
+        let late = eyre!(
+            "transaction ABCD was rejected with code 30: tx timeout height; current height: 105, \
+             timeout height: 100: tx timeout height"
+        );
+        assert!(is_timeout_height(&late));
+        assert!(!is_timeout_height(&mismatch));
+    }
+
+    #[test]
+    fn carries_the_report_in_failures() {
+        let confirmation = |hash: &str, code: u32| TxConfirmation {
+            hash: hash.to_string(),
+            height: 10,
+            code,
+            codespace: String::new(),
+            log: String::new(),
+            gas_wanted: 0,
+            gas_used: 0,
+            events: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let report = ExecutionReport {
+            confirmations: vec![confirmation("AA", 0), confirmation("CC", 5)],
+            batches: vec![0, 2],
+            ..Default::default()
+        };
+
+        assert_eq!(report.confirmation(2).unwrap().hash, "CC");
+        assert!(report.confirmation(1).is_none());
+
+        let error = report.into_result().unwrap_err();
+        let failed = error.downcast_ref::<ExecutionError>().unwrap();
+        assert_eq!(failed.report.batches, vec![0, 2]);
+        assert!(error.to_string().contains("2 batches included"));
+    }
+}
@@ -0,0 +1,159 @@
+//! Raising the fee of transactions that validators reject as underpriced.
+//!
+//! When a node rejects a transaction for paying too little, or for not paying enough to get into
+//! a full mempool, the [`Executor`](crate::executor::Executor) can raise the gas price by a fixed
+//! percentage and re-sign the transaction with the same sequence, up to a hard ceiling. Since the
+//! sequence is unchanged, at most one version of the transaction can ever be included.
+use std::str::FromStr;
+
+use eyre::{eyre, Report, Result};
+use ocular::{cosmrs::Coin, tx::FeeInfo};
+
+use crate::math::mul_div_rem;
+
+/// Error fragments of rejections that a higher fee may get past
+const FEE_REJECTIONS: &[&str] = &[
+    "insufficient fee",
+    "fee is too low",
+    "gas price too low",
+    "mempool is full",
+];
+
+/// Whether an error reports that a transaction paid too little to be accepted
+pub fn is_fee_rejection(error: &Report) -> bool {
+    let message = format!("{:#}", error).to_lowercase();
+    FEE_REJECTIONS.iter().any(|r| message.contains(r))
+}
+
+/// A gas price of `numerator / denominator` of the fee denom per unit of gas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasPrice {
+    pub numerator: u128,
+    pub denominator: u128,
+}
+
+impl GasPrice {
+    /// The fee for `gas` units of gas at this price, rounded up
+    pub fn fee_for(&self, gas: u64) -> u128 {
+        let (fee, remainder) = mul_div_rem(gas as u128, self.numerator, self.denominator);
+        fee + u128::from(remainder > 0)
+    }
+}
+
+impl FromStr for GasPrice {
+    type Err = Report;
+
+    /// Parses a decimal gas price such as `0.025`.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || eyre!("{} is not a valid gas price", s);
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(invalid());
+        }
+
+        let digits = format!("{}{}", whole, fraction);
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        Ok(GasPrice {
+            numerator: digits.parse().map_err(|_| invalid())?,
+            denominator: 10u128
+                .checked_pow(fraction.len() as u32)
+                .ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// How far the executor may raise fees.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeeBump {
+    /// Percentage the gas price is raised by on each rejection
+    pub step_percent: u128,
+    /// Highest gas price the executor will pay
+    pub max_gas_price: GasPrice,
+}
+
+impl FeeBump {
+    /// Returns `fee_info` with its fee raised by one step, capped at the ceiling, or `None` if the
+    /// fee is already at the ceiling. The fee always rises by at least one unit.
+    pub fn bump(&self, fee_info: &FeeInfo) -> Option<FeeInfo> {
+        let fee = fee_info.get_fee();
+        let gas_limit = fee_info.get_gas_limit();
+        let ceiling = self.max_gas_price.fee_for(gas_limit);
+        if fee.amount >= ceiling {
+            return None;
+        }
+
+        let (stepped, remainder) = mul_div_rem(fee.amount, 100 + self.step_percent, 100);
+        let amount = (stepped + u128::from(remainder > 0))
+            .max(fee.amount + 1)
+            .min(ceiling);
+        // Cloned so the fee payer and granter carry over
+        let mut bumped = fee_info.clone();
+        bumped.fee(Coin {
+            amount,
+            denom: fee.denom,
+        });
+
+        Some(bumped)
+    }
+}
+
+/// A fee raised by the executor, recorded for accounting.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeChange {
+    /// Index of the batch being re-signed
+    pub batch: usize,
+    pub sequence: u64,
+    pub previous: Coin,
+    pub new: Coin,
+    /// The rejection that caused the change
+    pub reason: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use ocular::cosmrs::{AccountId, Denom};
+
+    use super::*;
+
+    #[test]
+    fn parses_decimal_gas_prices() {
+        let price: GasPrice = "0.025".parse().unwrap();
+
+        assert_eq!((price.numerator, price.denominator), (25, 1_000));
+        assert_eq!(price.fee_for(200_001), 5_001);
+        assert_eq!("2".parse::<GasPrice>().unwrap().fee_for(10), 20);
+        assert!("0.0x".parse::<GasPrice>().is_err());
+        assert!(".".parse::<GasPrice>().is_err());
+    }
+
+    #[test]
+    fn bumps_fee_up_to_ceiling() {
+        let policy = FeeBump {
+            step_percent: 50,
+            max_gas_price: "0.05".parse().unwrap(),
+        };
+        let mut fee_info = FeeInfo::new(Coin {
+            amount: 5_000,
+            denom: Denom::from_str("uatom").unwrap(),
+        });
+        fee_info.gas_limit(200_000);
+        let granter = AccountId::from_str("cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08").unwrap();
+        fee_info.fee_granter(granter.clone());
+
+        let mut amounts = Vec::new();
+        let mut current = fee_info;
+        while let Some(bumped) = policy.bump(&current) {
+            amounts.push(bumped.get_fee().amount);
+            current = bumped;
+        }
+
+        assert_eq!(amounts, vec![7_500, 10_000]);
+        assert_eq!(current.get_fee_granter(), Some(granter));
+        assert!(is_fee_rejection(&eyre!(
+            "rejected with code 13: insufficient fees; got: 5000uatom required: 6000uatom"
+        )));
+    }
+}
@@ -0,0 +1,366 @@
+//! Exclusion and allow lists for airdrop recipients.
+//!
+//! Lists are TOML files of entries that match either a full address or an address prefix:
+//!
+//! ```toml
+//! label = "exchanges"
+//!
+//! [[entries]]
+//! address = "cosmos1..."
+//! label = "hot wallet"
+//!
+//! [[entries]]
+//! prefix = "cosmosvaloper"
+//! ```
+//!
+//! Address entries are compared by their underlying bytes, so an exclusion list of `cosmos1...`
+//! addresses also matches the same accounts on other chains, and a validator's operator address
+//! matches its account address. The entry's label, or the list's if the entry has none, is used as
+//! the reason in the [`FilterReport`].
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    str::FromStr,
+};
+
+use eyre::{eyre, Result};
+use ocular::cosmrs::AccountId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    distribution::split_pool,
+    payments::{read_payments_toml, Payment, PaymentsToml},
+    snapshot::Snapshot,
+};
+
+/// A single entry of an [`AddressList`]. Exactly one of `address` and `prefix` should be set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ListEntry {
+    pub address: Option<String>,
+    pub prefix: Option<String>,
+    pub label: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct AddressList {
+    /// Default label for entries without their own
+    pub label: Option<String>,
+    pub entries: Vec<ListEntry>,
+}
+
+/// Reads and deserializes a TOML file into an [`AddressList`]
+pub fn read_address_list(path: &str) -> Result<AddressList> {
+    let toml_string = fs::read_to_string(path)?;
+    Ok(toml::from_str(toml_string.as_str())?)
+}
+
+/// A recipient removed by a [`Filter`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Removal {
+    pub address: String,
+    pub amount: u128,
+    /// `None` for snapshot holdings
+    pub denom: Option<String>,
+    pub reason: String,
+}
+
+/// What a [`Filter`] removed, and how much was redistributed to the remaining recipients per
+/// denom.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterReport {
+    pub removed: Vec<Removal>,
+    pub redistributed: BTreeMap<String, u128>,
+}
+
+impl FilterReport {
+    /// Total removed per denom
+    pub fn removed_totals(&self) -> BTreeMap<String, u128> {
+        let mut totals = BTreeMap::<String, u128>::new();
+        for r in &self.removed {
+            if let Some(denom) = &r.denom {
+                *totals.entry(denom.clone()).or_insert(0) += r.amount;
+            }
+        }
+
+        totals
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Matcher {
+    /// address bytes (or the raw string, if it isn't valid bech32) -> reason
+    addresses: HashMap<Vec<u8>, String>,
+    prefixes: Vec<(String, String)>,
+}
+
+impl Matcher {
+    fn add(&mut self, list: &AddressList) -> Result<()> {
+        for entry in &list.entries {
+            let reason = entry
+                .label
+                .clone()
+                .or_else(|| list.label.clone())
+                .unwrap_or_else(|| "unlabeled".to_string());
+            match (&entry.address, &entry.prefix) {
+                (Some(address), None) => {
+                    self.addresses.insert(address_key(address), reason);
+                }
+                (None, Some(prefix)) => self.prefixes.push((prefix.clone(), reason)),
+                _ => {
+                    return Err(eyre!(
+                        "list entries need exactly one of address or prefix: {:?}",
+                        entry
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn matches(&self, address: &str) -> Option<&str> {
+        if let Some(reason) = self.addresses.get(&address_key(address)) {
+            return Some(reason);
+        }
+
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| address.starts_with(prefix.as_str()))
+            .map(|(_, reason)| reason.as_str())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.prefixes.is_empty()
+    }
+}
+
+fn address_key(address: &str) -> Vec<u8> {
+    match AccountId::from_str(address) {
+        Ok(id) => id.to_bytes(),
+        Err(_) => address.as_bytes().to_vec(),
+    }
+}
+
+/// Combined exclusion and allow lists. An address is removed if it matches any exclusion list,
+/// or if allow lists are present and it matches none of them.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    exclude: Matcher,
+    allow: Matcher,
+}
+
+impl Filter {
+    pub fn new(exclude: &[AddressList], allow: &[AddressList]) -> Result<Filter> {
+        let mut filter = Filter::default();
+        for list in exclude {
+            filter.exclude.add(list)?;
+        }
+        for list in allow {
+            filter.allow.add(list)?;
+        }
+
+        Ok(filter)
+    }
+
+    /// Loads exclusion and allow lists from TOML files.
+    pub fn from_files(exclude_paths: &[&str], allow_paths: &[&str]) -> Result<Filter> {
+        let exclude = exclude_paths
+            .iter()
+            .map(|p| read_address_list(p))
+            .collect::<Result<Vec<AddressList>>>()?;
+        let allow = allow_paths
+            .iter()
+            .map(|p| read_address_list(p))
+            .collect::<Result<Vec<AddressList>>>()?;
+
+        Filter::new(&exclude, &allow)
+    }
+
+    /// Returns the reason `address` is removed, if it is.
+    pub fn check(&self, address: &str) -> Option<String> {
+        if let Some(reason) = self.exclude.matches(address) {
+            return Some(format!("excluded: {}", reason));
+        }
+
+        if !self.allow.is_empty() && self.allow.matches(address).is_none() {
+            return Some("not on allow list".to_string());
+        }
+
+        None
+    }
+
+    /// Removes filtered recipients from `payments`. If `redistribute` is set, the removed amount
+    /// of each denom is split between the remaining payments of that denom in proportion to
+    /// their amounts, so the total paid out is unchanged.
+    pub fn apply_to_payments(
+        &self,
+        payments: Vec<Payment>,
+        redistribute: bool,
+    ) -> Result<(Vec<Payment>, FilterReport)> {
+        let mut report = FilterReport::default();
+        let mut kept = Vec::<Payment>::with_capacity(payments.len());
+        for p in payments {
+            match self.check(&p.recipient) {
+                Some(reason) => report.removed.push(Removal {
+                    address: p.recipient,
+                    amount: p.amount as u128,
+                    denom: Some(p.denom),
+                    reason,
+                }),
+                None => kept.push(p),
+            }
+        }
+
+        if redistribute {
+            for (denom, removed) in report.removed_totals() {
+                redistribute_amount(&mut kept, &denom, removed)?;
+                report.redistributed.insert(denom, removed);
+            }
+        }
+
+        Ok((kept, report))
+    }
+
+    /// Removes filtered addresses from a snapshot. Allocating a pool over the filtered snapshot
+    /// redistributes the removed addresses' share to everyone else.
+    pub fn apply_to_snapshot(&self, snapshot: Snapshot) -> (Snapshot, FilterReport) {
+        let mut report = FilterReport::default();
+        let Snapshot {
+            chain_id,
+            height,
+            holdings,
+        } = snapshot;
+        let holdings = holdings
+            .into_iter()
+            .filter(|h| match self.check(&h.address) {
+                Some(reason) => {
+                    report.removed.push(Removal {
+                        address: h.address.clone(),
+                        amount: h.amount,
+                        denom: None,
+                        reason,
+                    });
+                    false
+                }
+                None => true,
+            })
+            .collect();
+
+        (
+            Snapshot {
+                chain_id,
+                height,
+                holdings,
+            },
+            report,
+        )
+    }
+}
+
+/// Adds `amount` of `denom` to the payments of that denom, proportionally to their size.
+pub(crate) fn redistribute_amount(
+    payments: &mut [Payment],
+    denom: &str,
+    amount: u128,
+) -> Result<()> {
+    let indices: Vec<usize> = (0..payments.len())
+        .filter(|i| payments[*i].denom == denom)
+        .collect();
+    let weights: Vec<u128> = indices
+        .iter()
+        .map(|i| payments[*i].amount as u128)
+        .collect();
+    let shares = split_pool(amount, &weights)
+        .map_err(|e| eyre!("can't redistribute {} {}: {}", amount, denom, e))?;
+    for (i, share) in indices.into_iter().zip(shares) {
+        let total = payments[i].amount as u128 + share;
+        payments[i].amount = u64::try_from(total)
+            .map_err(|_| eyre!("payment to {} exceeds u64::MAX", payments[i].recipient))?;
+    }
+
+    Ok(())
+}
+
+/// Reads a payments TOML and applies `filter` to its payments.
+pub fn read_filtered_payments_toml(
+    path: &str,
+    filter: &Filter,
+    redistribute: bool,
+) -> Result<(PaymentsToml, FilterReport)> {
+    let mut payments_toml = read_payments_toml(path)?;
+    let (payments, report) =
+        filter.apply_to_payments(std::mem::take(&mut payments_toml.payments), redistribute)?;
+    payments_toml.payments = payments;
+
+    Ok((payments_toml, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATOR: &str = "cosmosvaloper1n6j7gnld9yxfyh6tflxhjjmt404zruuav297r5";
+    const ACCOUNT: &str = "cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08";
+
+    fn payment(recipient: &str, amount: u64) -> Payment {
+        Payment {
+            recipient: recipient.to_string(),
+            amount,
+            denom: "uatom".to_string(),
+        }
+    }
+
+    #[test]
+    fn excludes_and_redistributes() {
+        let exclusions: AddressList = toml::from_str(&format!(
+            r#"
+            label = "validators"
+
+            [[entries]]
+            address = "{}"
+
+            [[entries]]
+            prefix = "cosmos1treasury"
+            label = "treasury"
+            "#,
+            OPERATOR
+        ))
+        .unwrap();
+        let filter = Filter::new(&[exclusions], &[]).unwrap();
+        let payments = vec![
+            payment(ACCOUNT, 100),
+            payment("cosmos1treasuryxyz", 50),
+            payment("cosmos1a", 10),
+            payment("cosmos1b", 30),
+        ];
+        let (kept, report) = filter.apply_to_payments(payments, true).unwrap();
+
+        assert_eq!(
+            kept,
+            vec![payment("cosmos1a", 48), payment("cosmos1b", 142)]
+        );
+        assert_eq!(report.removed.len(), 2);
+        assert_eq!(report.removed[0].reason, "excluded: validators");
+        assert_eq!(report.removed[1].reason, "excluded: treasury");
+        assert_eq!(report.redistributed["uatom"], 150);
+    }
+
+    #[test]
+    fn allow_list_removes_everyone_else() {
+        let allowed = AddressList {
+            label: None,
+            entries: vec![ListEntry {
+                address: Some(ACCOUNT.to_string()),
+                ..Default::default()
+            }],
+        };
+        let filter = Filter::new(&[], &[allowed]).unwrap();
+        let (kept, report) = filter
+            .apply_to_payments(vec![payment(ACCOUNT, 1), payment("cosmos1a", 1)], false)
+            .unwrap();
+
+        assert_eq!(kept, vec![payment(ACCOUNT, 1)]);
+        assert_eq!(report.removed[0].reason, "not on allow list");
+        assert!(report.redistributed.is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! Low level gRPC helpers shared by the query paths that need more than [`ocular::QueryClient`]
+//! offers, such as pinning a query to a height or calling services missing from the bundled
+//! protos.
+use eyre::Result;
+use ocular::cosmrs::{
+    proto::cosmos::{
+        auth::v1beta1::{
+            query_client::QueryClient as AuthQueryClient, BaseAccount, ModuleAccount,
+            QueryAccountRequest, QueryAccountsRequest,
+        },
+        bank::v1beta1::{query_client::QueryClient as BankQueryClient, QueryAllBalancesRequest},
+        base::{query::v1beta1::PageRequest, v1beta1::Coin},
+        vesting::v1beta1::{
+            ContinuousVestingAccount, DelayedVestingAccount, PeriodicVestingAccount,
+            PermanentLockedAccount,
+        },
+    },
+    rpc::{Client, HttpClient},
+    tendermint::Time,
+    Any,
+};
+use prost::Message;
+use serde::de::DeserializeOwned;
+use tonic::{
+    client::Grpc,
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    metadata::MetadataValue,
+    transport::{Channel, Endpoint},
+    Request,
+};
+
+use crate::proto::{EthAccount, QuerySmartContractStateRequest, QuerySmartContractStateResponse};
+
+/// gRPC metadata key the Cosmos SDK reads to serve a query from a historical height
+pub(crate) const BLOCK_HEIGHT_HEADER: &str = "x-cosmos-block-height";
+
+/// Opens a channel to a gRPC endpoint
+pub(crate) async fn connect(grpc_endpoint: &str) -> Result<Channel> {
+    Ok(Endpoint::from_shared(grpc_endpoint.to_string())?
+        .connect()
+        .await?)
+}
+
+/// Wraps a query message in a request, pinned to `height` if one is given.
+pub(crate) fn request<T>(message: T, height: Option<u64>) -> Result<Request<T>> {
+    let mut request = Request::new(message);
+    if let Some(height) = height {
+        let value = MetadataValue::try_from(height.to_string())?;
+        request.metadata_mut().insert(BLOCK_HEIGHT_HEADER, value);
+    }
+
+    Ok(request)
+}
+
+/// Performs a unary call to `path` (e.g. `/cosmos.bank.v1beta1.Query/DenomOwners`)
+pub(crate) async fn unary<Req, Resp>(
+    channel: Channel,
+    path: &'static str,
+    request: Request<Req>,
+) -> Result<Resp>
+where
+    Req: Message + Send + 'static,
+    Resp: Message + Default + Send + 'static,
+{
+    let mut grpc = Grpc::new(channel);
+    grpc.ready().await?;
+    let codec = ProstCodec::<Req, Resp>::default();
+
+    Ok(grpc
+        .unary(request, PathAndQuery::from_static(path), codec)
+        .await?
+        .into_inner())
+}
+
+/// Runs a CosmWasm smart query against `contract` and deserializes the JSON response.
+pub(crate) async fn smart_query<T: DeserializeOwned>(
+    grpc_endpoint: &str,
+    contract: &str,
+    query: &serde_json::Value,
+) -> Result<T> {
+    let request = QuerySmartContractStateRequest {
+        address: contract.to_string(),
+        query_data: serde_json::to_vec(query)?,
+    };
+    let response: QuerySmartContractStateResponse = unary(
+        connect(grpc_endpoint).await?,
+        "/cosmwasm.wasm.v1.Query/SmartContractState",
+        Request::new(request),
+    )
+    .await?;
+
+    Ok(serde_json::from_slice(&response.data)?)
+}
+
+/// Fetches the account stored at `address`, or `None` if the chain has never seen it.
+pub(crate) async fn account(grpc_endpoint: &str, address: &str) -> Result<Option<Any>> {
+    let mut client = AuthQueryClient::connect(grpc_endpoint.to_string()).await?;
+    let request = QueryAccountRequest {
+        address: address.to_string(),
+    };
+
+    match client.account(request).await {
+        Ok(response) => Ok(response.into_inner().account),
+        Err(status) if status.code() == tonic::Code::NotFound => Ok(None),
+        Err(status) => Err(status.into()),
+    }
+}
+
+/// Decodes the [`BaseAccount`] inside an account returned by the auth module. Besides base
+/// accounts, this covers module accounts, vesting accounts and Ethermint-style `EthAccount`s.
+/// Returns `None` for other account types.
+pub(crate) fn base_account(account: &Any) -> Result<Option<BaseAccount>> {
+    let value = account.value.as_slice();
+    let base = match account.type_url.as_str() {
+        "/cosmos.auth.v1beta1.BaseAccount" => Some(BaseAccount::decode(value)?),
+        "/cosmos.auth.v1beta1.ModuleAccount" => ModuleAccount::decode(value)?.base_account,
+        "/cosmos.vesting.v1beta1.ContinuousVestingAccount" => {
+            ContinuousVestingAccount::decode(value)?
+                .base_vesting_account
+                .and_then(|v| v.base_account)
+        }
+        "/cosmos.vesting.v1beta1.DelayedVestingAccount" => DelayedVestingAccount::decode(value)?
+            .base_vesting_account
+            .and_then(|v| v.base_account),
+        "/cosmos.vesting.v1beta1.PeriodicVestingAccount" => PeriodicVestingAccount::decode(value)?
+            .base_vesting_account
+            .and_then(|v| v.base_account),
+        "/cosmos.vesting.v1beta1.PermanentLockedAccount" => PermanentLockedAccount::decode(value)?
+            .base_vesting_account
+            .and_then(|v| v.base_account),
+        "/ethermint.types.v1.EthAccount" | "/injective.types.v1beta1.EthAccount" => {
+            EthAccount::decode(value)?.base_account
+        }
+        _ => None,
+    };
+
+    Ok(base)
+}
+
+/// Fetches every balance held by `address`, at `height` if given.
+pub(crate) async fn balances(
+    grpc_endpoint: &str,
+    address: &str,
+    height: Option<u64>,
+) -> Result<Vec<Coin>> {
+    let mut client = BankQueryClient::connect(grpc_endpoint.to_string()).await?;
+    let mut balances = Vec::<Coin>::new();
+    let mut next_key = Vec::<u8>::new();
+    loop {
+        let request = QueryAllBalancesRequest {
+            address: address.to_string(),
+            pagination: Some(PageRequest {
+                key: next_key,
+                ..Default::default()
+            }),
+        };
+        let response = client
+            .all_balances(self::request(request, height)?)
+            .await?
+            .into_inner();
+        balances.extend(response.balances);
+
+        next_key = match response.pagination {
+            Some(page) if !page.next_key.is_empty() => page.next_key,
+            _ => return Ok(balances),
+        };
+    }
+}
+
+/// Returns the address of some account on chain, or `None` if none of the first few accounts
+/// wrap a base account. Used to learn the chain's bech32 prefix.
+pub(crate) async fn sample_address(grpc_endpoint: &str) -> Result<Option<String>> {
+    let mut client = AuthQueryClient::connect(grpc_endpoint.to_string()).await?;
+    let request = QueryAccountsRequest {
+        pagination: Some(PageRequest {
+            limit: 10,
+            ..Default::default()
+        }),
+    };
+    let accounts = client.accounts(request).await?.into_inner().accounts;
+
+    for account in accounts {
+        if let Some(base) = base_account(&account)? {
+            return Ok(Some(base.address));
+        }
+    }
+
+    Ok(None)
+}
+
+/// What a node reports about itself and its sync progress
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct NodeStatus {
+    pub chain_id: String,
+    pub latest_height: u64,
+    pub catching_up: bool,
+    /// Time of the latest block, in seconds since the Unix epoch
+    pub latest_block_time: u64,
+}
+
+/// Queries the status of the node at `rpc_endpoint`.
+pub(crate) async fn node_status(rpc_endpoint: &str) -> Result<NodeStatus> {
+    let status = HttpClient::new(rpc_endpoint)?.status().await?;
+
+    Ok(NodeStatus {
+        chain_id: status.node_info.network.to_string(),
+        latest_height: status.sync_info.latest_block_height.value(),
+        catching_up: status.sync_info.catching_up,
+        latest_block_time: status
+            .sync_info
+            .latest_block_time
+            .duration_since(Time::unix_epoch())?
+            .as_secs(),
+    })
+}
+
+/// Returns the chain ID and latest block height reported by the node at `rpc_endpoint`.
+pub(crate) async fn latest_height(rpc_endpoint: &str) -> Result<(String, u64)> {
+    let status = node_status(rpc_endpoint).await?;
+
+    Ok((status.chain_id, status.latest_height))
+}
+
+#[cfg(test)]
+mod tests {
+    use ocular::cosmrs::proto::cosmos::vesting::v1beta1::BaseVestingAccount;
+
+    use super::*;
+    use crate::proto::to_any;
+
+    #[test]
+    fn decodes_base_accounts_inside_wrappers() {
+        let base = BaseAccount {
+            address: "cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08".to_string(),
+            pub_key: None,
+            account_number: 7,
+            sequence: 42,
+        };
+        let vesting = to_any(
+            "/cosmos.vesting.v1beta1.ContinuousVestingAccount",
+            &ContinuousVestingAccount {
+                base_vesting_account: Some(BaseVestingAccount {
+                    base_account: Some(base.clone()),
+                    ..Default::default()
+                }),
+                start_time: 0,
+            },
+        );
+        let eth = to_any(
+            "/ethermint.types.v1.EthAccount",
+            &EthAccount {
+                base_account: Some(base.clone()),
+                code_hash: String::new(),
+            },
+        );
+
+        assert_eq!(base_account(&vesting).unwrap(), Some(base.clone()));
+        assert_eq!(base_account(&eth).unwrap(), Some(base));
+        assert_eq!(
+            base_account(&to_any(
+                "/cosmos.auth.v1beta1.Unknown",
+                &EthAccount::default()
+            ))
+            .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Helpers for working with ICS-20 IBC denominations.
+use std::collections::BTreeMap;
+
+use eyre::{eyre, Result};
+use ocular::cosmrs::proto::ibc::applications::transfer::v1::{
+    query_client::QueryClient as TransferQueryClient, QueryDenomTraceRequest,
+};
+use sha2::{Digest, Sha256};
+
+use crate::payments::Payment;
+
+/// The ICS-20 denomination trace of a token, e.g. `transfer/channel-141/uosmo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DenomTrace {
+    /// Port/channel pairs the token travelled through, e.g. `transfer/channel-141`
+    pub path: String,
+    pub base_denom: String,
+}
+
+impl DenomTrace {
+    /// Parses a trace of the form `{port}/{channel}/.../{base_denom}`. Returns `None` if the
+    /// string contains no port/channel hops, i.e. it is a native or already hashed denom.
+    pub fn parse(trace: &str) -> Option<DenomTrace> {
+        let segments: Vec<&str> = trace.split('/').collect();
+        let mut hops = 0;
+        while segments.len() > hops * 2 + 2 && segments[hops * 2 + 1].starts_with("channel-") {
+            hops += 1;
+        }
+
+        if hops == 0 {
+            return None;
+        }
+
+        let base_denom = segments[hops * 2..].join("/");
+        if base_denom.is_empty() {
+            return None;
+        }
+
+        Some(DenomTrace {
+            path: segments[..hops * 2].join("/"),
+            base_denom,
+        })
+    }
+
+    /// The full trace, `{path}/{base_denom}`
+    pub fn full_path(&self) -> String {
+        format!("{}/{}", self.path, self.base_denom)
+    }
+
+    /// Upper-case hex SHA256 hash of the full trace, as used in the `ibc/` denom
+    pub fn hash(&self) -> String {
+        let digest = Sha256::digest(self.full_path().as_bytes());
+        digest.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+
+    /// The on-chain denom of the voucher, `ibc/{hash}`
+    pub fn ibc_denom(&self) -> String {
+        format!("ibc/{}", self.hash())
+    }
+}
+
+/// Resolves a payment denom to the denom used on chain. Trace forms such as
+/// `transfer/channel-141/uosmo` are hashed into their `ibc/` denom; anything else is returned
+/// unchanged.
+pub fn resolve_denom(denom: &str) -> String {
+    match DenomTrace::parse(denom) {
+        Some(trace) => trace.ibc_denom(),
+        None => denom.to_string(),
+    }
+}
+
+/// Queries the chain's transfer module for the trace behind `ibc_denom` and checks that it
+/// matches `trace`.
+pub async fn verify_denom_trace(grpc_endpoint: &str, trace: &DenomTrace) -> Result<()> {
+    let mut client = TransferQueryClient::connect(grpc_endpoint.to_string()).await?;
+    let response = client
+        .denom_trace(QueryDenomTraceRequest { hash: trace.hash() })
+        .await?
+        .into_inner();
+    let on_chain = response
+        .denom_trace
+        .ok_or_else(|| eyre!("no denom trace found for {}", trace.ibc_denom()))?;
+
+    if on_chain.path != trace.path || on_chain.base_denom != trace.base_denom {
+        return Err(eyre!(
+            "denom trace mismatch for {}: expected {}, chain reports {}/{}",
+            trace.ibc_denom(),
+            trace.full_path(),
+            on_chain.path,
+            on_chain.base_denom
+        ));
+    }
+
+    Ok(())
+}
+
+/// Cross-checks every trace-form denom in `payments` against the chain.
+pub async fn verify_payment_denom_traces(grpc_endpoint: &str, payments: &[Payment]) -> Result<()> {
+    for entry in ibc_denom_report(payments) {
+        verify_denom_trace(grpc_endpoint, &entry.trace).await?;
+    }
+
+    Ok(())
+}
+
+/// An IBC asset in an airdrop, showing the readable trace alongside the hashed denom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IbcDenomEntry {
+    pub trace: DenomTrace,
+    pub denom: String,
+    pub total: u128,
+    pub payment_count: usize,
+}
+
+/// Summarizes the IBC assets paid out by `payments`. Only payments written in trace form are
+/// included, since a bare `ibc/` denom can't be reversed into its trace locally.
+pub fn ibc_denom_report(payments: &[Payment]) -> Vec<IbcDenomEntry> {
+    let mut entries = BTreeMap::<String, IbcDenomEntry>::new();
+    for p in payments {
+        if let Some(trace) = DenomTrace::parse(&p.denom) {
+            let denom = trace.ibc_denom();
+            let entry = entries.entry(denom.clone()).or_insert(IbcDenomEntry {
+                trace,
+                denom,
+                total: 0,
+                payment_count: 0,
+            });
+            entry.total += p.amount as u128;
+            entry.payment_count += 1;
+        }
+    }
+
+    entries.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_trace_into_ibc_denom() {
+        // ATOM on Osmosis
+        let trace = DenomTrace::parse("transfer/channel-0/uatom").unwrap();
+
+        assert_eq!(trace.path, "transfer/channel-0");
+        assert_eq!(trace.base_denom, "uatom");
+        assert_eq!(
+            trace.ibc_denom(),
+            "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2"
+        );
+    }
+
+    #[test]
+    fn parses_multi_hop_traces_and_ignores_native_denoms() {
+        let trace = DenomTrace::parse("transfer/channel-1/transfer/channel-2/gamm/pool/1").unwrap();
+
+        assert_eq!(trace.path, "transfer/channel-1/transfer/channel-2");
+        assert_eq!(trace.base_denom, "gamm/pool/1");
+        assert!(DenomTrace::parse("uatom").is_none());
+        assert!(DenomTrace::parse("gamm/pool/1").is_none());
+        assert!(DenomTrace::parse("factory/osmo1abc/token").is_none());
+        assert_eq!(
+            resolve_denom("ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2"),
+            "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Append-only JSON lines journals of the steps taken by multi-step airdrops.
+//!
+//! Each step is written and flushed as soon as it happens, so after a crash the journal shows
+//! exactly which transactions were sent and which funds may still need recovering. A crash while
+//! a step is being written leaves an unterminated last line; it is ignored when reading, and the
+//! next step recorded replaces it.
+use std::{
+    fs::{self, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// A journaled step and when it was recorded.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct JournalEntry<T> {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub step: T,
+}
+
+/// A journal file that steps are appended to.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    path: String,
+}
+
+impl Journal {
+    pub fn new(path: &str) -> Self {
+        Journal {
+            path: path.to_string(),
+        }
+    }
+
+    /// Appends `step` as one line, creating the file if needed.
+    pub fn record<T: Serialize>(&self, step: T) -> Result<()> {
+        let entry = JournalEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            step,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&self.path)?;
+        // Drop a line torn by a crash, rather than running this step into it
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let end = match contents.iter().rposition(|b| *b == b'\n') {
+            Some(i) => i as u64 + 1,
+            None => 0,
+        };
+        if end < contents.len() as u64 {
+            file.set_len(end)?;
+        }
+        file.seek(SeekFrom::Start(end))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+
+        Ok(file.sync_data()?)
+    }
+
+    /// Reads every entry recorded so far. A missing file has no entries, and an unterminated last
+    /// line, left by a crash while recording, is skipped.
+    pub fn entries<T: DeserializeOwned>(&self) -> Result<Vec<JournalEntry<T>>> {
+        if !Path::new(&self.path).exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&self.path)?;
+        let complete = match contents.rfind('\n') {
+            Some(end) => &contents[..end],
+            None => "",
+        };
+        complete
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+    #[serde(tag = "step", rename_all = "snake_case")]
+    enum Step {
+        Started,
+        Sent { tx_hash: String },
+    }
+
+    #[test]
+    fn appends_and_reads_back_steps() {
+        let path = std::env::temp_dir().join("cosmos_airdrop_journal_test.jsonl");
+        let _ = fs::remove_file(&path);
+        let journal = Journal::new(path.to_str().unwrap());
+        journal.record(Step::Started).unwrap();
+        journal
+            .record(Step::Sent {
+                tx_hash: "ABCD".to_string(),
+            })
+            .unwrap();
+
+        let steps: Vec<Step> = journal
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.step)
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            steps,
+            vec![
+                Step::Started,
+                Step::Sent {
+                    tx_hash: "ABCD".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_a_line_torn_by_a_crash() {
+        let path = std::env::temp_dir().join("cosmos_airdrop_torn_journal_test.jsonl");
+        let journal = Journal::new(path.to_str().unwrap());
+        fs::write(
+            &path,
+            "{\"timestamp\":1,\"step\":\"started\"}\n{\"timestamp\":2,\"st",
+        )
+        .unwrap();
+
+        let steps = |journal: &Journal| -> Vec<Step> {
+            journal
+                .entries()
+                .unwrap()
+                .into_iter()
+                .map(|e| e.step)
+                .collect()
+        };
+        assert_eq!(steps(&journal), vec![Step::Started]);
+
+        journal
+            .record(Step::Sent {
+                tx_hash: "ABCD".to_string(),
+            })
+            .unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(contents.lines().count(), 2);
+        assert!(contents.ends_with("\"tx_hash\":\"ABCD\"}\n"));
+    }
+}
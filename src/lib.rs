@@ -1,20 +1,49 @@
 //! A library for performing airdrops in the Cosmos ecosystem. Built on top of [`ocular`].
 use std::{collections::HashMap, str::FromStr};
 
-use eyre::Result;
+use eyre::{eyre, Result};
+use broadcast::{sign_and_confirm, ConfirmationOptions, TxConfirmation};
+use endpoints::Endpoints;
+use executor::{ExecutionReport, Executor, ExecutorOptions};
 use ocular::{
     chain::Context,
-    cosmrs::{
-        bank::MultiSendIo, rpc::endpoint::broadcast::tx_commit::Response, AccountId, Any, Coin,
-        Denom,
-    },
+    cosmrs::{bank::MultiSendIo, AccountId, Any, Coin, Denom},
     prelude::{AccountInfo, Authz, Bank},
-    tx::{FeeInfo, ModuleMsg, MsgClient, UnsignedTx},
-    QueryClient,
+    tx::{FeeInfo, ModuleMsg},
 };
 use payments::{read_payments_toml, Payment};
+use retry::RetryPolicy;
 
+pub mod address;
+pub mod broadcast;
+pub mod canary;
+pub mod cw20;
+pub mod daemon;
+pub mod distribution;
+pub mod dust;
+pub mod endpoints;
+pub mod executor;
+pub mod fees;
+pub mod filters;
+mod grpc;
+pub mod ibc;
+pub mod journal;
+mod math;
+pub mod merkle;
+pub mod nft;
 pub mod payments;
+pub mod plan;
+pub mod preflight;
+pub mod proposal;
+mod proto;
+pub mod retry;
+pub mod schedule;
+pub mod snapshot;
+pub mod staking;
+pub mod tokenfactory;
+pub mod transfer;
+pub mod vesting;
+pub mod workers;
 
 pub fn multi_send_from_payments(sender_address: &str, payments: Vec<Payment>) -> Result<Any> {
     let (inputs, outputs) = multi_send_args_from_payments(sender_address, payments)?;
@@ -25,14 +54,27 @@ pub fn multi_send_from_payments(sender_address: &str, payments: Vec<Payment>) ->
 /// Creates arguments for a MultiSend transaction from a vec of [`Payment`]. We require a single
 /// `Input` because, for Authz transactions, the tx will be considered to have multiple signers if
 /// there are multiple `Input`s, which is not allowed by the authz msg `MsgExec`.
+///
+/// Denoms written as an ICS-20 trace (e.g. `transfer/channel-141/uosmo`) are resolved to their
+/// `ibc/` hash. Every recipient must share the sender's bech32 prefix; use
+/// [`address::normalize_recipients`] to convert addresses taken from another chain.
 pub fn multi_send_args_from_payments(
     sender_address: &str,
     payments: Vec<Payment>,
 ) -> Result<(Vec<MultiSendIo>, Vec<MultiSendIo>)> {
+    let sender = AccountId::from_str(sender_address)?;
     let mut outputs = Vec::<MultiSendIo>::new();
     let mut coins_total = HashMap::<String, u128>::new();
     for p in payments {
-        let key = p.denom.clone();
+        if cw20::cw20_contract(&p.denom).is_some() {
+            return Err(eyre!(
+                "payment to {} is a CW20 token and can't be sent with MultiSend",
+                p.recipient
+            ));
+        }
+
+        let denom = ibc::resolve_denom(&p.denom);
+        let key = denom.clone();
         let value = p.amount;
         if coins_total.contains_key(&key) {
             coins_total.insert(key.clone(), coins_total.get(&key).unwrap() + value as u128);
@@ -40,10 +82,19 @@ pub fn multi_send_args_from_payments(
             coins_total.insert(key, value as u128);
         }
 
+        let recipient = AccountId::from_str(&p.recipient)?;
+        if recipient.prefix() != sender.prefix() {
+            return Err(eyre!(
+                "recipient {} does not match the sender's address prefix {}",
+                p.recipient,
+                sender.prefix()
+            ));
+        }
+
         let o = MultiSendIo {
-            address: AccountId::from_str(&p.recipient)?,
+            address: recipient,
             coins: vec![Coin {
-                denom: Denom::from_str(&p.denom)?,
+                denom: Denom::from_str(&denom)?,
                 amount: p.amount as u128,
             }],
         };
@@ -60,7 +111,7 @@ pub fn multi_send_args_from_payments(
         })
         .collect::<Result<Vec<Coin>>>();
     let input = vec![MultiSendIo {
-        address: AccountId::from_str(sender_address)?,
+        address: sender,
         coins: coins_input?,
     }];
 
@@ -74,17 +125,76 @@ pub async fn execute_airdrop(
     chain_context: &Context,
     rpc_endpoint: &str,
     grpc_endpoint: &str,
-) -> Result<Response> {
+) -> Result<TxConfirmation> {
     let address = &sender.address(&chain_context.prefix)?;
     let msg = multi_send_from_payments(address, payments)?;
-    let mut qclient = QueryClient::new(rpc_endpoint, grpc_endpoint)?;
-    let mut mclient = MsgClient::new(rpc_endpoint)?;
-    let mut tx = UnsignedTx::new();
-    tx.add_msg(msg);
-    tx.sign(sender, fee_info, &chain_context, &mut qclient)
-        .await?
-        .broadcast_commit(&mut mclient)
+    sign_and_broadcast(
+        sender,
+        vec![msg],
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+/// Sends payments from `sender` as one `MsgMultiSend` per batch of `batch_size` payments. The
+/// batches are broadcast without waiting for the previous ones to commit. If sending stops
+/// part way, the error is an [`executor::ExecutionError`] saying which batches were included.
+pub async fn execute_batched_airdrop(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<TxConfirmation>> {
+    let address = &sender.address(&chain_context.prefix)?;
+    let batches = multi_send_batches(address, payments, batch_size)?;
+    sign_and_broadcast_batches(
+        sender,
+        batches,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+/// Like [`execute_batched_airdrop`], but fails over between the healthy nodes of `endpoints`.
+/// The report includes any fee changes made by the executor. A failed transaction is returned as
+/// an [`executor::ExecutionError`] carrying the report.
+pub async fn execute_airdrop_with_failover(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    endpoints: Endpoints,
+    options: ExecutorOptions,
+) -> Result<ExecutionReport> {
+    let address = &sender.address(&chain_context.prefix)?;
+    let batches = multi_send_batches(address, payments, batch_size)?;
+    Executor::with_endpoints(sender, fee_info, chain_context, endpoints)
+        .with_options(options)
+        .execute(batches)
         .await
+        .and_then(ExecutionReport::into_result)
+}
+
+/// Splits payments into batches of one `MsgMultiSend` each
+pub(crate) fn multi_send_batches(
+    sender_address: &str,
+    payments: Vec<Payment>,
+    batch_size: usize,
+) -> Result<Vec<Vec<Any>>> {
+    payments::batch_payments(payments, batch_size)
+        .into_iter()
+        .map(|batch| Ok(vec![multi_send_from_payments(sender_address, batch)?]))
+        .collect()
 }
 
 pub async fn execute_airdrop_from_toml(
@@ -93,8 +203,9 @@ pub async fn execute_airdrop_from_toml(
     chain_context: &Context,
     rpc_endpoint: &str,
     grpc_endpoint: &str,
-) -> Result<Response> {
+) -> Result<TxConfirmation> {
     let payments_toml = read_payments_toml(path)?;
+    payments_toml.ensure_no_nft_payments()?;
     let sender = AccountInfo::from_pem(&payments_toml.signing_key)?;
     execute_airdrop(
         &sender,
@@ -115,21 +226,22 @@ pub async fn execute_delegated_airdrop(
     chain_context: &Context,
     rpc_endpoint: &str,
     grpc_endpoint: &str,
-) -> Result<Response> {
+) -> Result<TxConfirmation> {
     let inner_msg = multi_send_from_payments(granter, payments)?;
     let msg = Authz::Exec {
         grantee: &grantee.address(&chain_context.prefix)?,
         msgs: vec![inner_msg],
     }
     .into_any()?;
-    let mut qclient = QueryClient::new(rpc_endpoint, grpc_endpoint)?;
-    let mut mclient = MsgClient::new(rpc_endpoint)?;
-    let mut tx = UnsignedTx::new();
-    tx.add_msg(msg);
-    tx.sign(grantee, fee_info, &chain_context, &mut qclient)
-        .await?
-        .broadcast_commit(&mut mclient)
-        .await
+    sign_and_broadcast(
+        grantee,
+        vec![msg],
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
 }
 
 pub async fn execute_delegated_airdrop_from_toml(
@@ -139,8 +251,9 @@ pub async fn execute_delegated_airdrop_from_toml(
     chain_context: &Context,
     rpc_endpoint: &str,
     grpc_endpoint: &str,
-) -> Result<Response> {
+) -> Result<TxConfirmation> {
     let payments_toml = read_payments_toml(path)?;
+    payments_toml.ensure_no_nft_payments()?;
     let grantee = AccountInfo::from_pem(&payments_toml.signing_key)?;
     execute_delegated_airdrop(
         granter,
@@ -154,6 +267,60 @@ pub async fn execute_delegated_airdrop_from_toml(
     .await
 }
 
+/// Checks the node with [`preflight::check_endpoint`], then signs `msgs` into a single
+/// transaction, broadcasts it and waits for it to be included in a block with the default
+/// [`ConfirmationOptions`] and [`RetryPolicy`]. Checks that had to be skipped are listed in the
+/// confirmation's `warnings`.
+pub(crate) async fn sign_and_broadcast(
+    signer: &AccountInfo,
+    msgs: Vec<Any>,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<TxConfirmation> {
+    let warnings = preflight::check_endpoint(chain_context, rpc_endpoint, grpc_endpoint).await?;
+    let mut confirmation = sign_and_confirm(
+        signer,
+        msgs,
+        &fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+        &ConfirmationOptions::default(),
+        &RetryPolicy::default(),
+    )
+    .await?;
+    confirmation.warnings = warnings;
+
+    Ok(confirmation)
+}
+
+/// Sends each batch of messages as its own transaction through a pipelined [`Executor`] and
+/// returns their confirmations in batch order. Returns an error if any of them fails; once
+/// anything was broadcast, the error is an [`executor::ExecutionError`] with the report of the
+/// batches that were included.
+pub(crate) async fn sign_and_broadcast_batches(
+    signer: &AccountInfo,
+    batches: Vec<Vec<Any>>,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<TxConfirmation>> {
+    Executor::new(
+        signer,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .execute(batches)
+    .await?
+    .into_result()
+    .map(|report| report.confirmations)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,6 +345,14 @@ mod tests {
         assert_eq!(input_total, output_total);
     }
 
+    #[test]
+    fn rejects_recipients_with_foreign_prefix() {
+        let sender_address = "osmo1n6j7gnld9yxfyh6tflxhjjmt404zruuap9zme4";
+        let payments = generate_payments_single_denom("uosmo");
+
+        assert!(multi_send_args_from_payments(sender_address, payments).is_err());
+    }
+
     fn generate_payments_single_denom(denom: &str) -> Vec<Payment> {
         let mut output = Vec::<Payment>::new();
         for _ in 0..10 {
@@ -0,0 +1,94 @@
+//! Integer arithmetic helpers for amounts that may not fit intermediate results in a [`u128`].
+
+/// Computes `floor(a * b / c)` without overflowing the intermediate product. Panics if `c` is
+/// zero or the result doesn't fit in a [`u128`].
+pub(crate) fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    mul_div_rem(a, b, c).0
+}
+
+/// Like [`mul_div`], but also returns the remainder `a * b mod c`.
+pub(crate) fn mul_div_rem(a: u128, b: u128, c: u128) -> (u128, u128) {
+    assert!(c != 0, "division by zero");
+
+    if let Some(product) = a.checked_mul(b) {
+        return (product / c, product % c);
+    }
+
+    let (hi, lo) = wide_mul(a, b);
+    assert!(hi < c, "mul_div result overflows u128");
+
+    // shift-subtract long division of the 256-bit product by c
+    let mut remainder = hi;
+    let mut quotient = 0u128;
+    for i in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((lo >> i) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= c {
+            remainder = remainder.wrapping_sub(c);
+            quotient |= 1;
+        }
+    }
+
+    (quotient, remainder)
+}
+
+/// Integer square root, rounded down.
+pub(crate) fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+
+    // Newton's method from an initial guess that is guaranteed to be above the root
+    let mut x = 1u128 << (n.ilog2() / 2 + 1);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+/// Full 256-bit product of two [`u128`]s as `(high, low)` halves.
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    let mask = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & mask);
+    let (b_hi, b_lo) = (b >> 64, b & mask);
+
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+
+    let cross = (lo_lo >> 64) + (hi_lo & mask) + (lo_hi & mask);
+    let lo = (cross << 64) | (lo_lo & mask);
+    let hi = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (cross >> 64);
+
+    (hi, lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_handles_wide_products() {
+        assert_eq!(mul_div(10, 20, 3), 66);
+        assert_eq!(mul_div(u128::MAX, 3, 3), u128::MAX);
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), u128::MAX);
+        assert_eq!(
+            mul_div(10u128.pow(30), 10u128.pow(20), 10u128.pow(25)),
+            10u128.pow(25)
+        );
+        assert_eq!(mul_div_rem(u128::MAX, 7, 10), (u128::MAX / 10 * 7 + 3, 5));
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(15), 3);
+        assert_eq!(isqrt(16), 4);
+        assert_eq!(isqrt(u128::MAX), u64::MAX as u128);
+    }
+}
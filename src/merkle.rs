@@ -0,0 +1,255 @@
+//! Merkle trees for claim-based airdrops through CosmWasm distributor contracts.
+//!
+//! Leaves and proofs follow the `cw20-merkle-airdrop` contract: each leaf is
+//! `sha256(address + amount)`, sibling hashes are sorted before being concatenated and hashed, and
+//! the root and proofs are hex encoded. Odd nodes are carried up to the next layer unhashed, as
+//! the contract's reference tooling does.
+use std::{collections::BTreeMap, fs};
+
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::payments::Payment;
+
+type Hash = [u8; 32];
+
+/// A single recipient's claim and the proof the contract needs to verify it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Claim {
+    pub address: String,
+    /// Stringified like the contract's `Uint128` amounts
+    pub amount: String,
+    pub proof: Vec<String>,
+}
+
+/// A claim airdrop built from a payment list.
+#[derive(Clone, Debug)]
+pub struct MerkleAirdrop {
+    denom: String,
+    /// address -> amount, with duplicate recipients merged
+    amounts: BTreeMap<String, u128>,
+    /// Bottom layer first. The leaves are sorted so the root doesn't depend on payment order.
+    layers: Vec<Vec<Hash>>,
+}
+
+/// The JSON exported for a stage of a merkle airdrop.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct MerkleAirdropExport {
+    pub merkle_root: String,
+    pub denom: String,
+    pub total_amount: String,
+    pub claims: Vec<Claim>,
+}
+
+impl MerkleAirdrop {
+    /// Builds the tree for `payments`, which must all be of a single denom since each contract
+    /// stage distributes one asset. Multiple payments to the same recipient are merged.
+    pub fn new(payments: &[Payment]) -> Result<MerkleAirdrop> {
+        let denom = match payments.first() {
+            Some(p) => p.denom.clone(),
+            None => return Err(eyre!("can't build a merkle airdrop without payments")),
+        };
+
+        let mut amounts = BTreeMap::<String, u128>::new();
+        for p in payments {
+            if p.denom != denom {
+                return Err(eyre!(
+                    "merkle airdrops distribute a single denom, found {} and {}",
+                    denom,
+                    p.denom
+                ));
+            }
+            *amounts.entry(p.recipient.clone()).or_insert(0) += p.amount as u128;
+        }
+
+        let mut leaves: Vec<Hash> = amounts
+            .iter()
+            .map(|(address, amount)| leaf(address, *amount))
+            .collect();
+        leaves.sort_unstable();
+
+        let mut layers = vec![leaves];
+        while layers.last().map(|l| l.len()).unwrap_or(0) > 1 {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => hash_pair(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+            layers.push(next);
+        }
+
+        Ok(MerkleAirdrop {
+            denom,
+            amounts,
+            layers,
+        })
+    }
+
+    pub fn denom(&self) -> &str {
+        &self.denom
+    }
+
+    /// Hex encoded root to register with the contract
+    pub fn root(&self) -> String {
+        hex::encode(self.layers.last().unwrap()[0])
+    }
+
+    /// Sum of all claimable amounts, i.e. what the contract must be funded with
+    pub fn total_amount(&self) -> u128 {
+        self.amounts.values().sum()
+    }
+
+    /// Looks up the claimable amount and proof for `address`.
+    pub fn claim(&self, address: &str) -> Option<Claim> {
+        let amount = *self.amounts.get(address)?;
+        let mut index = self.layers[0].binary_search(&leaf(address, amount)).ok()?;
+        let mut proof = Vec::<String>::new();
+        for layer in &self.layers[..self.layers.len() - 1] {
+            if let Some(sibling) = layer.get(index ^ 1) {
+                proof.push(hex::encode(sibling));
+            }
+            index /= 2;
+        }
+
+        Some(Claim {
+            address: address.to_string(),
+            amount: amount.to_string(),
+            proof,
+        })
+    }
+
+    /// Proof for `address`, if it has a claim
+    pub fn proof(&self, address: &str) -> Option<Vec<String>> {
+        self.claim(address).map(|c| c.proof)
+    }
+
+    /// Every claim, sorted by address
+    pub fn claims(&self) -> Vec<Claim> {
+        self.amounts
+            .keys()
+            .filter_map(|address| self.claim(address))
+            .collect()
+    }
+
+    pub fn export(&self) -> MerkleAirdropExport {
+        MerkleAirdropExport {
+            merkle_root: self.root(),
+            denom: self.denom.clone(),
+            total_amount: self.total_amount().to_string(),
+            claims: self.claims(),
+        }
+    }
+}
+
+/// Serializes the root and every claim's proof into a JSON file at the specified path
+pub fn write_merkle_airdrop_json(path: &str, airdrop: &MerkleAirdrop) -> Result<()> {
+    let json = serde_json::to_string_pretty(&airdrop.export())?;
+    Ok(fs::write(path, json)?)
+}
+
+/// Reads an exported merkle airdrop JSON file
+pub fn read_merkle_airdrop_json(path: &str) -> Result<MerkleAirdropExport> {
+    let json = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Verifies a claim against a hex encoded root the same way the contract does.
+pub fn verify_claim(root: &str, claim: &Claim) -> Result<bool> {
+    let amount: u128 = claim.amount.parse()?;
+    let mut hash = leaf(&claim.address, amount);
+    for p in &claim.proof {
+        let sibling: Hash = hex::decode(p)?
+            .try_into()
+            .map_err(|_| eyre!("proof element {} is not 32 bytes", p))?;
+        hash = hash_pair(&hash, &sibling);
+    }
+
+    Ok(hex::encode(hash) == root.to_lowercase())
+}
+
+fn leaf(address: &str, amount: u128) -> Hash {
+    Sha256::digest(format!("{}{}", address, amount).as_bytes()).into()
+}
+
+fn hash_pair(a: &Hash, b: &Hash) -> Hash {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = Sha256::new();
+    hasher.update(first);
+    hasher.update(second);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payments(count: usize) -> Vec<Payment> {
+        (0..count)
+            .map(|i| Payment {
+                recipient: format!("wasm1recipient{}", i),
+                amount: 100 * (i as u64 + 1),
+                denom: "ujuno".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn builds_verifiable_proofs() {
+        for count in [1, 2, 3, 7, 8] {
+            let airdrop = MerkleAirdrop::new(&payments(count)).unwrap();
+            let root = airdrop.root();
+
+            assert_eq!(airdrop.claims().len(), count);
+            for claim in airdrop.claims() {
+                assert!(verify_claim(&root, &claim).unwrap());
+            }
+        }
+
+        let airdrop = MerkleAirdrop::new(&payments(3)).unwrap();
+        let mut claim = airdrop.claim("wasm1recipient0").unwrap();
+        claim.amount = "101".to_string();
+
+        assert!(!verify_claim(&airdrop.root(), &claim).unwrap());
+        assert!(airdrop.proof("wasm1nobody").is_none());
+    }
+
+    #[test]
+    fn root_matches_hand_built_tree() {
+        let single = MerkleAirdrop::new(&payments(1)).unwrap();
+
+        assert_eq!(single.root(), hex::encode(leaf("wasm1recipient0", 100)));
+        assert!(single.claim("wasm1recipient0").unwrap().proof.is_empty());
+
+        let mut leaves = [
+            leaf("wasm1recipient0", 100),
+            leaf("wasm1recipient1", 200),
+            leaf("wasm1recipient2", 300),
+        ];
+        leaves.sort_unstable();
+        let expected = hash_pair(&hash_pair(&leaves[0], &leaves[1]), &leaves[2]);
+
+        assert_eq!(
+            MerkleAirdrop::new(&payments(3)).unwrap().root(),
+            hex::encode(expected)
+        );
+    }
+
+    #[test]
+    fn merges_duplicates_and_rejects_mixed_denoms() {
+        let mut p = payments(2);
+        p.push(p[0].clone());
+        let airdrop = MerkleAirdrop::new(&p).unwrap();
+
+        assert_eq!(airdrop.claim("wasm1recipient0").unwrap().amount, "200");
+        assert_eq!(airdrop.total_amount(), 400);
+
+        p[1].denom = "uatom".to_string();
+        assert!(MerkleAirdrop::new(&p).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Airdrops of NFTs from `x/nft` classes and CW721 collections.
+//!
+//! An [`NftPayment`] sends from an `x/nft` class unless its `class_id` is a CW721 contract address
+//! prefixed with `cw721:`, e.g. `cw721:stars1...`. CW721 tokens can either be transferred from the
+//! sender's holdings or minted directly to the recipient.
+use std::{fs, str::FromStr};
+
+use eyre::{eyre, Result};
+use ocular::{
+    chain::Context,
+    cosmrs::{AccountId, Any},
+    prelude::{AccountInfo, Authz},
+    tx::{FeeInfo, ModuleMsg},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    broadcast::TxConfirmation,
+    cw20::execute_contract_msg,
+    payments::{batch_payments, Payment, PaymentsToml},
+    proto::{to_any, MsgNftSend},
+    sign_and_broadcast_batches,
+};
+
+/// Class id prefix marking an NFT payment as a CW721 token
+pub const CW721_PREFIX: &str = "cw721:";
+
+const MSG_NFT_SEND_TYPE_URL: &str = "/cosmos.nft.v1beta1.MsgSend";
+
+/// Represents the transfer of a single NFT to a recipient.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct NftPayment {
+    pub recipient: String,
+    /// `x/nft` class id, or `cw721:` followed by the collection's contract address
+    pub class_id: String,
+    pub token_id: String,
+    /// Metadata URI set on CW721 tokens when they're minted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+/// Whether CW721 tokens are transferred from the sender or minted by it. `x/nft` has no mint
+/// message, so its tokens are always transferred.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cw721Action {
+    Transfer,
+    Mint,
+}
+
+/// Returns the collection contract address if `class_id` refers to a CW721 collection.
+pub fn cw721_contract(class_id: &str) -> Option<&str> {
+    class_id.strip_prefix(CW721_PREFIX)
+}
+
+/// Builds one message per NFT payment: an `x/nft` `MsgSend`, or a CW721 `transfer_nft` or `mint`
+/// execution depending on `action`. Every recipient must share the sender's address prefix.
+pub fn nft_msgs_from_payments(
+    sender_address: &str,
+    payments: &[NftPayment],
+    action: Cw721Action,
+) -> Result<Vec<Any>> {
+    let sender = AccountId::from_str(sender_address)?;
+    payments
+        .iter()
+        .map(|p| {
+            if AccountId::from_str(&p.recipient)?.prefix() != sender.prefix() {
+                return Err(eyre!(
+                    "recipient {} does not match the sender's address prefix {}",
+                    p.recipient,
+                    sender.prefix()
+                ));
+            }
+
+            let contract = match cw721_contract(&p.class_id) {
+                Some(c) => c,
+                None => {
+                    let msg = MsgNftSend {
+                        class_id: p.class_id.clone(),
+                        id: p.token_id.clone(),
+                        sender: sender_address.to_string(),
+                        receiver: p.recipient.clone(),
+                    };
+                    return Ok(to_any(MSG_NFT_SEND_TYPE_URL, &msg));
+                }
+            };
+
+            let msg = match action {
+                Cw721Action::Transfer => json!({
+                    "transfer_nft": {
+                        "recipient": p.recipient,
+                        "token_id": p.token_id,
+                    }
+                }),
+                Cw721Action::Mint => json!({
+                    "mint": {
+                        "token_id": p.token_id,
+                        "owner": p.recipient,
+                        "token_uri": p.uri,
+                        "extension": {},
+                    }
+                }),
+            };
+            execute_contract_msg(sender_address, contract, &msg)
+        })
+        .collect()
+}
+
+/// Sends NFT payments from `sender`, one transaction per batch of `batch_size` payments.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_nft_airdrop(
+    sender: &AccountInfo,
+    payments: Vec<NftPayment>,
+    action: Cw721Action,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<TxConfirmation>> {
+    let address = sender.address(&chain_context.prefix)?;
+    let batches = batch_payments(payments, batch_size)
+        .iter()
+        .map(|batch| nft_msgs_from_payments(&address, batch, action))
+        .collect::<Result<Vec<Vec<Any>>>>()?;
+    sign_and_broadcast_batches(
+        sender,
+        batches,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+/// Like [`execute_nft_airdrop`], but sends NFTs owned (or minted) by `granter` with each batch
+/// wrapped in an authz `MsgExec` signed by `grantee`.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_delegated_nft_airdrop(
+    granter: &str,
+    grantee: &AccountInfo,
+    payments: Vec<NftPayment>,
+    action: Cw721Action,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<TxConfirmation>> {
+    let grantee_address = grantee.address(&chain_context.prefix)?;
+    let batches = batch_payments(payments, batch_size)
+        .iter()
+        .map(|batch| {
+            let msg = Authz::Exec {
+                grantee: &grantee_address,
+                msgs: nft_msgs_from_payments(granter, batch, action)?,
+            }
+            .into_any()?;
+            Ok(vec![msg])
+        })
+        .collect::<Result<Vec<Vec<Any>>>>()?;
+    sign_and_broadcast_batches(
+        grantee,
+        batches,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+/// Serializes fungible and NFT payments into a toml at the specified path
+pub fn write_payments_with_nfts_toml(
+    path: &str,
+    sender_key_path: &str,
+    payments: Vec<Payment>,
+    nft_payments: Vec<NftPayment>,
+) -> Result<()> {
+    let toml_obj = PaymentsToml {
+        signing_key: sender_key_path.to_string(),
+        payments,
+        nft_payments,
+    };
+    let toml_string = toml::to_string(&toml_obj)?;
+    Ok(fs::write(path, toml_string)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::proto::MsgExecuteContract;
+
+    const SENDER: &str = "cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08";
+
+    #[test]
+    fn builds_nft_msgs() {
+        let payments = vec![
+            NftPayment {
+                recipient: SENDER.to_string(),
+                class_id: "badges".to_string(),
+                token_id: "1".to_string(),
+                uri: None,
+            },
+            NftPayment {
+                recipient: SENDER.to_string(),
+                class_id: format!("{}cosmos1collection", CW721_PREFIX),
+                token_id: "2".to_string(),
+                uri: Some("ipfs://badge".to_string()),
+            },
+        ];
+        let msgs = nft_msgs_from_payments(SENDER, &payments, Cw721Action::Mint).unwrap();
+        let send = MsgNftSend::decode(msgs[0].value.as_slice()).unwrap();
+        let mint = MsgExecuteContract::decode(msgs[1].value.as_slice()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&mint.msg).unwrap();
+
+        assert_eq!(msgs[0].type_url, MSG_NFT_SEND_TYPE_URL);
+        assert_eq!((send.class_id.as_str(), send.id.as_str()), ("badges", "1"));
+        assert_eq!(mint.contract, "cosmos1collection");
+        assert_eq!(body["mint"]["owner"], SENDER);
+        assert_eq!(body["mint"]["token_uri"], "ipfs://badge");
+
+        let msgs = nft_msgs_from_payments(SENDER, &payments[1..], Cw721Action::Transfer).unwrap();
+        let transfer = MsgExecuteContract::decode(msgs[0].value.as_slice()).unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&transfer.msg).unwrap();
+
+        assert_eq!(
+            body,
+            json!({ "transfer_nft": { "recipient": SENDER, "token_id": "2" } })
+        );
+    }
+
+    #[test]
+    fn reads_nft_payments_from_payments_toml() {
+        let toml_string = r#"
+            signing_key = "~/.keys/sender_key"
+
+            [[payments]]
+            recipient = "bob"
+            amount = 100
+            denom = "dollarbucks"
+
+            [[nft_payments]]
+            recipient = "alice"
+            class_id = "badges"
+            token_id = "7"
+        "#;
+        let parsed: PaymentsToml = toml::from_str(toml_string).unwrap();
+
+        assert_eq!(parsed.payments.len(), 1);
+        assert_eq!(parsed.nft_payments[0].token_id, "7");
+        assert!(parsed.nft_payments[0].uri.is_none());
+        assert!(parsed.ensure_no_nft_payments().is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use std::fs;
 
-use eyre::Result;
+use eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
 
+use crate::nft::NftPayment;
+
 /// Represents a payments of a single denomination to a recipient.
 ///
 /// Note: [`Payment`] uses [`u64`] for `amount` because the [`toml`] crate does not support serialization
@@ -18,6 +20,24 @@ pub struct Payment {
 pub struct PaymentsToml {
     pub signing_key: String,
     pub payments: Vec<Payment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nft_payments: Vec<NftPayment>,
+}
+
+impl PaymentsToml {
+    /// Fails if the file lists NFT payments, for entry points that only send fungible payments.
+    /// NFTs are sent with [`execute_nft_airdrop`](crate::nft::execute_nft_airdrop).
+    pub fn ensure_no_nft_payments(&self) -> Result<()> {
+        if !self.nft_payments.is_empty() {
+            return Err(eyre!(
+                "the payments file lists {} NFT payments, which this airdrop would not send; \
+                 send them with execute_nft_airdrop",
+                self.nft_payments.len()
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 /// Reads and deserializes a TOML file into a [`PaymentsToml`]
@@ -35,11 +55,25 @@ pub fn write_payments_toml(
     let toml_obj = PaymentsToml {
         signing_key: sender_key_path.to_string(),
         payments,
+        nft_payments: Vec::new(),
     };
     let toml_string = toml::to_string(&toml_obj)?;
     Ok(fs::write(path, toml_string)?)
 }
 
+/// Splits payments into batches of at most `batch_size`, preserving order. Each batch is sent
+/// in its own transaction.
+pub fn batch_payments<T>(payments: Vec<T>, batch_size: usize) -> Vec<Vec<T>> {
+    let batch_size = batch_size.max(1);
+    let mut batches = Vec::<Vec<T>>::new();
+    let mut iter = payments.into_iter().peekable();
+    while iter.peek().is_some() {
+        batches.push(iter.by_ref().take(batch_size).collect());
+    }
+
+    batches
+}
+
 #[cfg(test)]
 mod tests {
     use std::{fs::Permissions, os::unix::prelude::PermissionsExt, path::Path};
@@ -89,6 +123,7 @@ mod tests {
         let expected_result = PaymentsToml {
             signing_key: sender_key.clone(),
             payments: payments.clone(),
+            nft_payments: Vec::new(),
         };
 
         // Write and read payments toml
@@ -114,4 +149,20 @@ mod tests {
         let result = std::panic::catch_unwind(|| std::fs::metadata(path_string).unwrap());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn batches_payments_in_order() {
+        let payments: Vec<Payment> = (0..5)
+            .map(|i| Payment {
+                recipient: format!("recipient{}", i),
+                amount: i,
+                denom: "dingos".to_string(),
+            })
+            .collect();
+        let batches = batch_payments(payments.clone(), 2);
+
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[2], vec![payments[4].clone()]);
+        assert_eq!(batches.concat(), payments);
+    }
 }
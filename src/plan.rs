@@ -0,0 +1,149 @@
+//! A human-readable report of everything decided while preparing an airdrop's payments.
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    dust::{DustPolicy, DustReport},
+    filters::FilterReport,
+    ibc::{ibc_denom_report, resolve_denom, IbcDenomEntry},
+    payments::Payment,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlanReport {
+    pub payment_count: usize,
+    /// Total to be paid per on-chain denom
+    pub totals: BTreeMap<String, u128>,
+    pub ibc_denoms: Vec<IbcDenomEntry>,
+    /// Warnings raised while converting recipient addresses
+    pub address_warnings: Vec<String>,
+    pub filter: Option<FilterReport>,
+    pub dust: Option<DustReport>,
+}
+
+impl PlanReport {
+    /// Summarizes the final payment list. Attach the reports of the steps that produced it by
+    /// setting the corresponding fields.
+    pub fn new(payments: &[Payment]) -> Self {
+        let mut totals = BTreeMap::<String, u128>::new();
+        for p in payments {
+            *totals.entry(resolve_denom(&p.denom)).or_insert(0) += p.amount as u128;
+        }
+
+        PlanReport {
+            payment_count: payments.len(),
+            totals,
+            ibc_denoms: ibc_denom_report(payments),
+            ..Default::default()
+        }
+    }
+}
+
+impl fmt::Display for PlanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "payments: {}", self.payment_count)?;
+        for (denom, total) in &self.totals {
+            writeln!(f, "total: {} {}", total, denom)?;
+        }
+
+        for entry in &self.ibc_denoms {
+            writeln!(
+                f,
+                "ibc denom: {} = {} ({} in {} payments)",
+                entry.denom,
+                entry.trace.full_path(),
+                entry.total,
+                entry.payment_count
+            )?;
+        }
+
+        for warning in &self.address_warnings {
+            writeln!(f, "address warning: {}", warning)?;
+        }
+
+        if let Some(filter) = &self.filter {
+            writeln!(f, "removed by filters: {}", filter.removed.len())?;
+            for r in &filter.removed {
+                match &r.denom {
+                    Some(denom) => {
+                        writeln!(f, "  {} {} {}: {}", r.address, r.amount, denom, r.reason)?
+                    }
+                    None => writeln!(f, "  {} {}: {}", r.address, r.amount, r.reason)?,
+                }
+            }
+            for (denom, amount) in &filter.redistributed {
+                writeln!(f, "  redistributed {} {}", amount, denom)?;
+            }
+        }
+
+        if let Some(dust) = &self.dust {
+            writeln!(
+                f,
+                "below dust threshold of {}: {}",
+                dust.threshold,
+                dust.decisions.len()
+            )?;
+            for d in &dust.decisions {
+                let action = match d.policy {
+                    DustPolicy::Redistribute => "redistributed",
+                    DustPolicy::RoundUp => "rounded up",
+                    DustPolicy::CarryOver => "carried over",
+                };
+                writeln!(f, "  {} {} {}: {}", d.recipient, d.amount, d.denom, action)?;
+            }
+            for (denom, amount) in &dust.carried_in {
+                writeln!(f, "  carried in {} {}", amount, denom)?;
+            }
+            for (denom, amount) in &dust.redistributed {
+                writeln!(f, "  redistributed {} {}", amount, denom)?;
+            }
+            for (denom, amount) in &dust.rounded_up {
+                writeln!(f, "  rounded up by {} {}", amount, denom)?;
+            }
+            for (denom, amount) in &dust.carried_over {
+                writeln!(f, "  carried over {} {}", amount, denom)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dust::{DustPolicy, DustRule};
+
+    #[test]
+    fn reports_ibc_denoms_and_dust_decisions() {
+        let payments = vec![
+            Payment {
+                recipient: "a".to_string(),
+                amount: 500,
+                denom: "transfer/channel-0/uatom".to_string(),
+            },
+            Payment {
+                recipient: "b".to_string(),
+                amount: 5,
+                denom: "transfer/channel-0/uatom".to_string(),
+            },
+        ];
+        let outcome = DustRule {
+            threshold: 10,
+            policy: DustPolicy::CarryOver,
+        }
+        .apply(payments, vec![])
+        .unwrap();
+        let mut report = PlanReport::new(&outcome.payments);
+        report.dust = Some(outcome.report);
+        let rendered = report.to_string();
+
+        assert_eq!(
+            report.totals["ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2"],
+            500
+        );
+        assert!(rendered.contains(
+            "ibc denom: ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2 = transfer/channel-0/uatom"
+        ));
+        assert!(rendered.contains("  b 5 transfer/channel-0/uatom: carried over"));
+    }
+}
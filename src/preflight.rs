@@ -0,0 +1,105 @@
+//! Checks that the node an airdrop is about to use serves the chain it will be signed for.
+//!
+//! Signing with a mainnet [`Context`] while pointing at a testnet node, or the other way around,
+//! would otherwise only show up as a confusing signature or address error after broadcasting.
+//! The address prefix check is best effort, since many public nodes don't serve the account
+//! listing it relies on; when it can't run, the reason is returned as a warning instead.
+use std::str::FromStr;
+
+use eyre::{eyre, Result};
+use ocular::{chain::Context, cosmrs::AccountId};
+
+use crate::grpc::{self, NodeStatus};
+
+/// Checks that a node reports `chain_id` and is not catching up.
+pub(crate) fn check_status(status: &NodeStatus, chain_id: &str) -> Result<()> {
+    if status.chain_id != chain_id {
+        return Err(eyre!(
+            "chain id mismatch: node reports {} but the context is for {}",
+            status.chain_id,
+            chain_id
+        ));
+    }
+    if status.catching_up {
+        return Err(eyre!(
+            "node is still catching up at height {}",
+            status.latest_height
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that `address`, returned by the chain, uses the context's bech32 prefix.
+pub(crate) fn check_prefix(address: &str, prefix: &str) -> Result<()> {
+    let chain_prefix = AccountId::from_str(address)?.prefix().to_string();
+    if chain_prefix != prefix {
+        return Err(eyre!(
+            "bech32 prefix mismatch: chain addresses use {} but the context uses {}",
+            chain_prefix,
+            prefix
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks the prefix of an address from the chain at `grpc_endpoint` against the context. If the
+/// accounts can't be listed, or none can be decoded, the check is skipped and a warning saying
+/// why is returned.
+pub(crate) async fn check_chain_prefix(
+    chain_context: &Context,
+    grpc_endpoint: &str,
+) -> Result<Option<String>> {
+    match grpc::sample_address(grpc_endpoint).await {
+        Ok(Some(address)) => check_prefix(&address, &chain_context.prefix).map(|()| None),
+        Ok(None) => Ok(Some(
+            "skipped the bech32 prefix check: no accounts to sample".to_string(),
+        )),
+        Err(e) => Ok(Some(format!(
+            "skipped the bech32 prefix check: {} did not list accounts: {}",
+            grpc_endpoint, e
+        ))),
+    }
+}
+
+/// Queries the node at `rpc_endpoint` and `grpc_endpoint` and checks that it serves the chain in
+/// `chain_context`, is synced, and uses the context's address prefix. Returns a warning for each
+/// check that had to be skipped.
+pub async fn check_endpoint(
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<String>> {
+    let status = grpc::node_status(rpc_endpoint).await?;
+    check_status(&status, &chain_context.id)
+        .map_err(|e| eyre!("{} can't be used: {}", rpc_endpoint, e))?;
+
+    Ok(check_chain_prefix(chain_context, grpc_endpoint)
+        .await?
+        .into_iter()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_mismatched_chains() {
+        let status = NodeStatus {
+            chain_id: "theta-testnet-001".to_string(),
+            latest_height: 100,
+            catching_up: false,
+            latest_block_time: 0,
+        };
+        let error = check_status(&status, "cosmoshub-4").unwrap_err();
+
+        assert!(error.to_string().contains("chain id mismatch"));
+        assert!(check_status(&status, "theta-testnet-001").is_ok());
+
+        let address = "osmo1n6j7gnld9yxfyh6tflxhjjmt404zruuap9zme4";
+        assert!(check_prefix(address, "osmo").is_ok());
+        assert!(check_prefix(address, "cosmos").is_err());
+    }
+}
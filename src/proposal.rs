@@ -0,0 +1,238 @@
+//! Airdrops funded through governance, carried as the messages of an `x/gov` or `x/group`
+//! proposal instead of being signed by a hot key.
+//!
+//! The payments are turned into messages exactly as for a direct airdrop, with the proposal's
+//! executor (the gov module account or a group policy) as the sender. The resulting proposal is
+//! exported unsigned so it can be signed offline or by a multisig.
+use std::{collections::BTreeMap, fs};
+
+use eyre::{eyre, Result};
+use ocular::cosmrs::{
+    proto::cosmos::{base::v1beta1::Coin, tx::v1beta1::TxBody},
+    Any,
+};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ibc::resolve_denom,
+    multi_send_from_payments,
+    payments::Payment,
+    proto::{to_any, GovMsgSubmitProposal, GroupMsgSubmitProposal, MsgCommunityPoolSpend},
+};
+
+const GOV_SUBMIT_PROPOSAL_TYPE_URL: &str = "/cosmos.gov.v1.MsgSubmitProposal";
+const GROUP_SUBMIT_PROPOSAL_TYPE_URL: &str = "/cosmos.group.v1.MsgSubmitProposal";
+const COMMUNITY_POOL_SPEND_TYPE_URL: &str = "/cosmos.distribution.v1beta1.MsgCommunityPoolSpend";
+
+/// Tendermint's default `max_tx_bytes`
+pub const DEFAULT_MAX_TX_BYTES: usize = 1_048_576;
+
+/// Bytes set aside for the signatures, fee and signer info added when the proposal is signed
+const SIGNING_OVERHEAD_BYTES: usize = 1_024;
+
+/// Where the proposal's payments come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Funding {
+    /// A `MsgMultiSend` from an account the proposal can move funds from, such as the gov module
+    /// account or a group policy
+    Account(String),
+    /// One `MsgCommunityPoolSpend` per recipient, authorized by the gov module account
+    CommunityPool { authority: String },
+}
+
+/// Human-readable description of the proposal.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ProposalMetadata {
+    pub title: String,
+    pub summary: String,
+    /// Arbitrary metadata, usually a link to an off-chain description
+    #[serde(default)]
+    pub metadata: String,
+}
+
+/// Builds the messages the proposal executes to pay out `payments`.
+pub fn proposal_msgs(funding: &Funding, payments: Vec<Payment>) -> Result<Vec<Any>> {
+    match funding {
+        Funding::Account(address) => Ok(vec![multi_send_from_payments(address, payments)?]),
+        Funding::CommunityPool { authority } => {
+            let mut recipients = BTreeMap::<String, BTreeMap<String, u128>>::new();
+            for p in payments {
+                *recipients
+                    .entry(p.recipient)
+                    .or_default()
+                    .entry(resolve_denom(&p.denom))
+                    .or_insert(0) += p.amount as u128;
+            }
+
+            Ok(recipients
+                .into_iter()
+                .map(|(recipient, coins)| {
+                    let msg = MsgCommunityPoolSpend {
+                        authority: authority.clone(),
+                        recipient,
+                        amount: coins
+                            .into_iter()
+                            .map(|(denom, amount)| Coin {
+                                denom,
+                                amount: amount.to_string(),
+                            })
+                            .collect(),
+                    };
+
+                    to_any(COMMUNITY_POOL_SPEND_TYPE_URL, &msg)
+                })
+                .collect())
+        }
+    }
+}
+
+/// Wraps `msgs` in an `x/gov` v1 `MsgSubmitProposal` from `proposer`.
+pub fn gov_proposal(
+    proposer: &str,
+    initial_deposit: Vec<Coin>,
+    msgs: Vec<Any>,
+    metadata: &ProposalMetadata,
+) -> Any {
+    let msg = GovMsgSubmitProposal {
+        messages: msgs,
+        initial_deposit,
+        proposer: proposer.to_string(),
+        metadata: metadata.metadata.clone(),
+        title: metadata.title.clone(),
+        summary: metadata.summary.clone(),
+    };
+
+    to_any(GOV_SUBMIT_PROPOSAL_TYPE_URL, &msg)
+}
+
+/// Wraps `msgs` in an `x/group` `MsgSubmitProposal` to `group_policy_address`. With `try_exec`,
+/// the proposal executes as soon as it is submitted if the proposers' votes already pass it.
+pub fn group_proposal(
+    group_policy_address: &str,
+    proposers: &[&str],
+    msgs: Vec<Any>,
+    metadata: &ProposalMetadata,
+    try_exec: bool,
+) -> Any {
+    let msg = GroupMsgSubmitProposal {
+        group_policy_address: group_policy_address.to_string(),
+        proposers: proposers.iter().map(|p| p.to_string()).collect(),
+        metadata: metadata.metadata.clone(),
+        messages: msgs,
+        exec: i32::from(try_exec),
+        title: metadata.title.clone(),
+        summary: metadata.summary.clone(),
+    };
+
+    to_any(GROUP_SUBMIT_PROPOSAL_TYPE_URL, &msg)
+}
+
+/// Encodes the body of the unsigned transaction submitting `proposal`.
+pub fn proposal_tx_body(proposal: Any, memo: &str) -> Vec<u8> {
+    TxBody {
+        messages: vec![proposal],
+        memo: memo.to_string(),
+        ..Default::default()
+    }
+    .encode_to_vec()
+}
+
+/// Checks that a transaction with body `tx_body` stays under `max_tx_bytes` once signed.
+pub fn check_tx_size(tx_body: &[u8], max_tx_bytes: usize) -> Result<()> {
+    let size = tx_body.len() + SIGNING_OVERHEAD_BYTES;
+    if size > max_tx_bytes {
+        return Err(eyre!(
+            "proposal transaction is about {} bytes, over the {} byte limit; split the payments \
+             across several proposals",
+            size,
+            max_tx_bytes
+        ));
+    }
+
+    Ok(())
+}
+
+/// An unsigned proposal transaction, exported for offline or multisig signing.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ProposalExport {
+    pub type_url: String,
+    pub title: String,
+    pub summary: String,
+    pub metadata: String,
+    pub message_count: usize,
+    /// Hex encoded `cosmos.tx.v1beta1.TxBody`
+    pub tx_body: String,
+}
+
+impl ProposalExport {
+    /// Builds the export of a proposal returned by [`gov_proposal`] or [`group_proposal`],
+    /// checking that it fits in a transaction.
+    pub fn new(
+        proposal: Any,
+        message_count: usize,
+        metadata: &ProposalMetadata,
+        max_tx_bytes: usize,
+    ) -> Result<ProposalExport> {
+        let type_url = proposal.type_url.clone();
+        let tx_body = proposal_tx_body(proposal, "");
+        check_tx_size(&tx_body, max_tx_bytes)?;
+
+        Ok(ProposalExport {
+            type_url,
+            title: metadata.title.clone(),
+            summary: metadata.summary.clone(),
+            metadata: metadata.metadata.clone(),
+            message_count,
+            tx_body: hex::encode(tx_body),
+        })
+    }
+}
+
+/// Serializes a proposal export into a JSON file at the specified path
+pub fn write_proposal_json(path: &str, export: &ProposalExport) -> Result<()> {
+    let json = serde_json::to_string_pretty(export)?;
+    Ok(fs::write(path, json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTHORITY: &str = "cosmos10d07y265gmmuvt4z0w9aw880jnsr700j6zn9kn";
+
+    fn payments(count: u64) -> Vec<Payment> {
+        (0..count)
+            .map(|i| Payment {
+                recipient: format!("cosmos1recipient{}", i % 2),
+                amount: 10 + i,
+                denom: "uatom".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wraps_community_pool_spends_in_gov_proposal() {
+        let funding = Funding::CommunityPool {
+            authority: AUTHORITY.to_string(),
+        };
+        let msgs = proposal_msgs(&funding, payments(3)).unwrap();
+        let spend = MsgCommunityPoolSpend::decode(msgs[0].value.as_slice()).unwrap();
+        let metadata = ProposalMetadata {
+            title: "Airdrop".to_string(),
+            summary: "Pays the airdrop from the community pool".to_string(),
+            metadata: String::new(),
+        };
+        let proposal = gov_proposal(AUTHORITY, vec![], msgs, &metadata);
+        let decoded = GovMsgSubmitProposal::decode(proposal.value.as_slice()).unwrap();
+
+        assert_eq!(spend.recipient, "cosmos1recipient0");
+        assert_eq!(spend.amount[0].amount, "22");
+        assert_eq!(decoded.messages.len(), 2);
+        assert_eq!(decoded.title, "Airdrop");
+
+        let export = ProposalExport::new(proposal.clone(), 2, &metadata, DEFAULT_MAX_TX_BYTES);
+        assert_eq!(export.unwrap().type_url, GOV_SUBMIT_PROPOSAL_TYPE_URL);
+        assert!(ProposalExport::new(proposal, 2, &metadata, 1_100).is_err());
+    }
+}
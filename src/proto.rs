@@ -0,0 +1,222 @@
+//! Protobuf messages for Cosmos SDK modules and chains that aren't covered by the protos bundled
+//! with [`ocular::cosmrs`].
+use ocular::cosmrs::{
+    proto::{
+        cosmos::{
+            auth::v1beta1::BaseAccount,
+            base::{
+                query::v1beta1::{PageRequest, PageResponse},
+                v1beta1::Coin,
+            },
+        },
+        ibc::core::client::v1::Height,
+    },
+    Any,
+};
+use prost::Message;
+
+/// Encodes a message into an [`Any`] with the given type URL
+pub(crate) fn to_any<M: Message>(type_url: &str, msg: &M) -> Any {
+    Any {
+        type_url: type_url.to_string(),
+        value: msg.encode_to_vec(),
+    }
+}
+
+/// `ethermint.types.v1.EthAccount`, also used by Injective as `injective.types.v1beta1.EthAccount`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EthAccount {
+    #[prost(message, optional, tag = "1")]
+    pub base_account: Option<BaseAccount>,
+    #[prost(string, tag = "2")]
+    pub code_hash: String,
+}
+
+/// `cosmos.bank.v1beta1.QueryDenomOwnersRequest` (Cosmos SDK v0.46+)
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryDenomOwnersRequest {
+    #[prost(string, tag = "1")]
+    pub denom: String,
+    #[prost(message, optional, tag = "2")]
+    pub pagination: Option<PageRequest>,
+}
+
+/// `cosmos.bank.v1beta1.DenomOwner`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DenomOwner {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    #[prost(message, optional, tag = "2")]
+    pub balance: Option<Coin>,
+}
+
+/// `cosmos.bank.v1beta1.QueryDenomOwnersResponse`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryDenomOwnersResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub denom_owners: Vec<DenomOwner>,
+    #[prost(message, optional, tag = "2")]
+    pub pagination: Option<PageResponse>,
+}
+
+/// `cosmwasm.wasm.v1.MsgExecuteContract`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgExecuteContract {
+    #[prost(string, tag = "1")]
+    pub sender: String,
+    #[prost(string, tag = "2")]
+    pub contract: String,
+    /// JSON encoded contract message
+    #[prost(bytes = "vec", tag = "3")]
+    pub msg: Vec<u8>,
+    #[prost(message, repeated, tag = "5")]
+    pub funds: Vec<Coin>,
+}
+
+/// `cosmwasm.wasm.v1.QuerySmartContractStateRequest`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QuerySmartContractStateRequest {
+    #[prost(string, tag = "1")]
+    pub address: String,
+    /// JSON encoded query
+    #[prost(bytes = "vec", tag = "2")]
+    pub query_data: Vec<u8>,
+}
+
+/// `cosmwasm.wasm.v1.QuerySmartContractStateResponse`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QuerySmartContractStateResponse {
+    /// JSON encoded response
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+/// `cosmos.nft.v1beta1.MsgSend` (Cosmos SDK v0.46+)
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgNftSend {
+    #[prost(string, tag = "1")]
+    pub class_id: String,
+    #[prost(string, tag = "2")]
+    pub id: String,
+    #[prost(string, tag = "3")]
+    pub sender: String,
+    #[prost(string, tag = "4")]
+    pub receiver: String,
+}
+
+/// `ibc.applications.transfer.v1.MsgTransfer`, including the `memo` field added in ibc-go v5
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgTransfer {
+    #[prost(string, tag = "1")]
+    pub source_port: String,
+    #[prost(string, tag = "2")]
+    pub source_channel: String,
+    #[prost(message, optional, tag = "3")]
+    pub token: Option<Coin>,
+    #[prost(string, tag = "4")]
+    pub sender: String,
+    #[prost(string, tag = "5")]
+    pub receiver: String,
+    #[prost(message, optional, tag = "6")]
+    pub timeout_height: Option<Height>,
+    /// Nanoseconds since the epoch, or 0 for none
+    #[prost(uint64, tag = "7")]
+    pub timeout_timestamp: u64,
+    #[prost(string, tag = "8")]
+    pub memo: String,
+}
+
+/// `cosmos.vesting.v1beta1.MsgCreateVestingAccount`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgCreateVestingAccount {
+    #[prost(string, tag = "1")]
+    pub from_address: String,
+    #[prost(string, tag = "2")]
+    pub to_address: String,
+    #[prost(message, repeated, tag = "3")]
+    pub amount: Vec<Coin>,
+    #[prost(int64, tag = "4")]
+    pub end_time: i64,
+    #[prost(bool, tag = "5")]
+    pub delayed: bool,
+}
+
+/// `cosmos.vesting.v1beta1.Period`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct VestingPeriod {
+    #[prost(int64, tag = "1")]
+    pub length: i64,
+    #[prost(message, repeated, tag = "2")]
+    pub amount: Vec<Coin>,
+}
+
+/// `cosmos.vesting.v1beta1.MsgCreatePeriodicVestingAccount` (Cosmos SDK v0.46+)
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgCreatePeriodicVestingAccount {
+    #[prost(string, tag = "1")]
+    pub from_address: String,
+    #[prost(string, tag = "2")]
+    pub to_address: String,
+    #[prost(int64, tag = "3")]
+    pub start_time: i64,
+    #[prost(message, repeated, tag = "4")]
+    pub vesting_periods: Vec<VestingPeriod>,
+}
+
+/// `osmosis.tokenfactory.v1beta1.MsgMint`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgMint {
+    #[prost(string, tag = "1")]
+    pub sender: String,
+    #[prost(message, optional, tag = "2")]
+    pub amount: Option<Coin>,
+}
+
+/// `cosmos.gov.v1.MsgSubmitProposal`, including the `title` and `summary` fields added in Cosmos
+/// SDK v0.47
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GovMsgSubmitProposal {
+    #[prost(message, repeated, tag = "1")]
+    pub messages: Vec<Any>,
+    #[prost(message, repeated, tag = "2")]
+    pub initial_deposit: Vec<Coin>,
+    #[prost(string, tag = "3")]
+    pub proposer: String,
+    #[prost(string, tag = "4")]
+    pub metadata: String,
+    #[prost(string, tag = "5")]
+    pub title: String,
+    #[prost(string, tag = "6")]
+    pub summary: String,
+}
+
+/// `cosmos.group.v1.MsgSubmitProposal`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GroupMsgSubmitProposal {
+    #[prost(string, tag = "1")]
+    pub group_policy_address: String,
+    #[prost(string, repeated, tag = "2")]
+    pub proposers: Vec<String>,
+    #[prost(string, tag = "3")]
+    pub metadata: String,
+    #[prost(message, repeated, tag = "4")]
+    pub messages: Vec<Any>,
+    /// `cosmos.group.v1.Exec`: 0 for unspecified, 1 to try executing on submission
+    #[prost(int32, tag = "5")]
+    pub exec: i32,
+    #[prost(string, tag = "6")]
+    pub title: String,
+    #[prost(string, tag = "7")]
+    pub summary: String,
+}
+
+/// `cosmos.distribution.v1beta1.MsgCommunityPoolSpend` (Cosmos SDK v0.47+)
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MsgCommunityPoolSpend {
+    #[prost(string, tag = "1")]
+    pub authority: String,
+    #[prost(string, tag = "2")]
+    pub recipient: String,
+    #[prost(message, repeated, tag = "3")]
+    pub amount: Vec<Coin>,
+}
@@ -0,0 +1,151 @@
+//! Retrying of transient query and broadcast failures.
+//!
+//! Failures are classified as [`Failure::Safe`] when the request can't have changed any state,
+//! such as a refused connection or a full mempool, and [`Failure::Unsafe`] otherwise. Only safe
+//! failures are retried. Broadcasts are additionally checked by transaction hash before every
+//! retry, so a transaction that was accepted despite the error is never sent twice.
+use std::{future::Future, time::Duration};
+
+use eyre::{Report, Result};
+use rand::Rng;
+use tokio::time::sleep;
+
+/// Error fragments of failures that happen before a request reaches the application, and so are
+/// safe to retry.
+const SAFE_FAILURES: &[&str] = &[
+    "connection refused",
+    "connection reset",
+    "broken pipe",
+    "transport error",
+    "timed out",
+    "deadline has elapsed",
+    "mempool is full",
+    "too many requests",
+    "service unavailable",
+    "status: unavailable",
+];
+
+/// Whether a failed request may be retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// The request had no effect and can be repeated
+    Safe,
+    /// The request was rejected, or may have had an effect
+    Unsafe,
+}
+
+/// Classifies an error by its message.
+pub fn classify(error: &Report) -> Failure {
+    let message = format!("{:#}", error).to_lowercase();
+    if SAFE_FAILURES.iter().any(|f| message.contains(f)) {
+        Failure::Safe
+    } else {
+        Failure::Unsafe
+    }
+}
+
+/// How many times to attempt a request, and how long to wait between attempts. The delay grows
+/// by `multiplier` up to `max_backoff`, and is randomly varied by up to `jitter` (a fraction of
+/// the delay) so that many clients don't retry in lockstep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: u32,
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before retrying after failed attempt number `attempt`, counting from zero
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let base = self
+            .initial_backoff
+            .saturating_mul(self.multiplier.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return base;
+        }
+
+        base.mul_f64(rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter))
+    }
+
+    /// Runs `operation` until it succeeds, fails unsafely, or runs out of attempts, returning
+    /// the last error.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if classify(&e) == Failure::Unsafe => return Err(e),
+                Err(e) if attempt + 1 >= self.max_attempts => {
+                    return Err(e.wrap_err(format!("gave up after {} attempts", attempt + 1)))
+                }
+                Err(_) => {
+                    sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::eyre;
+
+    use super::*;
+
+    #[test]
+    fn only_retries_failures_without_effects() {
+        let refused = eyre!("error trying to connect: tcp connect error: Connection refused");
+        let full = eyre!("mempool is full: number of txs 5000 (max: 5000)");
+        let rejected = eyre!("transaction ABCD was rejected with code 5: insufficient funds");
+
+        assert_eq!(classify(&refused), Failure::Safe);
+        assert_eq!(classify(&full), Failure::Safe);
+        assert_eq!(classify(&rejected), Failure::Unsafe);
+    }
+
+    #[test]
+    fn backs_off_exponentially_with_bounded_jitter() {
+        let policy = RetryPolicy::default();
+        let backoffs: Vec<Duration> = (0..8).map(|a| policy.backoff(a)).collect();
+
+        assert!(backoffs[0] >= Duration::from_millis(400));
+        assert!(backoffs[0] <= Duration::from_millis(600));
+        assert!(backoffs[2] >= Duration::from_millis(1_600));
+        assert!(backoffs[7] <= Duration::from_secs(12));
+
+        let exact = RetryPolicy {
+            jitter: 0.0,
+            ..policy
+        };
+        assert_eq!(exact.backoff(3), Duration::from_secs(4));
+    }
+}
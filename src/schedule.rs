@@ -0,0 +1,135 @@
+//! Airdrops that start at a block height or block time observed on chain.
+//!
+//! The scheduler polls the node's latest block rather than the local clock, so a distribution
+//! meant to land right after an upgrade or at an announced time follows the chain even if the
+//! chain halts or its blocks drift. Optionally, every transaction carries a `timeout_height` so
+//! batches that fall too far behind are rejected instead of landing late.
+use std::time::Duration;
+
+use eyre::Result;
+use ocular::{chain::Context, prelude::AccountInfo, tx::FeeInfo};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::{
+    executor::{ExecutionReport, Executor, ExecutorOptions},
+    grpc::{self, NodeStatus},
+    multi_send_batches,
+    payments::Payment,
+    retry::{classify, Failure},
+};
+
+/// When a scheduled airdrop starts.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// Once the chain has committed this block height
+    Height(u64),
+    /// Once the chain has committed a block at or after this time, in seconds since the Unix epoch
+    Time(u64),
+}
+
+impl Trigger {
+    /// Whether the latest block reported in `status` satisfies the trigger
+    pub(crate) fn reached(&self, status: &NodeStatus) -> bool {
+        match self {
+            Trigger::Height(height) => status.latest_height >= *height,
+            Trigger::Time(time) => status.latest_block_time >= *time,
+        }
+    }
+}
+
+/// A trigger and how to wait for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub trigger: Trigger,
+    /// How often to check the latest block
+    pub poll_interval: Duration,
+    /// If set, transactions are rejected once this many blocks have passed since the trigger
+    pub window_blocks: Option<u64>,
+}
+
+impl Schedule {
+    pub fn new(trigger: Trigger) -> Self {
+        Schedule {
+            trigger,
+            poll_interval: Duration::from_secs(2),
+            window_blocks: None,
+        }
+    }
+}
+
+/// Polls the node at `rpc_endpoint` until `trigger` is reached, returning the latest height at
+/// that point. Transient query failures are retried at the next poll.
+pub async fn wait_for_trigger(
+    rpc_endpoint: &str,
+    trigger: &Trigger,
+    poll_interval: Duration,
+) -> Result<u64> {
+    loop {
+        match grpc::node_status(rpc_endpoint).await {
+            Ok(status) if trigger.reached(&status) => return Ok(status.latest_height),
+            Ok(_) => {}
+            Err(e) if classify(&e) == Failure::Safe => {}
+            Err(e) => return Err(e),
+        }
+
+        sleep(poll_interval).await;
+    }
+}
+
+/// Waits for `schedule`'s trigger, then sends `payments` from `sender` in batches of
+/// `batch_size`. The payments are checked before waiting, so a malformed list fails straight away.
+///
+/// With a window, batches that miss it aren't an error: sending stops at the first late batch and
+/// the report's `expired` lists the batches that weren't paid. A transaction that is included but
+/// fails is returned as an [`ExecutionError`](crate::executor::ExecutionError) with the report.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_scheduled_airdrop(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    batch_size: usize,
+    schedule: &Schedule,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<ExecutionReport> {
+    let address = sender.address(&chain_context.prefix)?;
+    let batches = multi_send_batches(&address, payments, batch_size)?;
+
+    let height = wait_for_trigger(rpc_endpoint, &schedule.trigger, schedule.poll_interval).await?;
+    let options = ExecutorOptions {
+        timeout_height: schedule.window_blocks.map(|w| height + w),
+        ..Default::default()
+    };
+
+    Executor::new(sender, fee_info, chain_context, rpc_endpoint, grpc_endpoint)
+        .with_options(options)
+        .execute(batches)
+        .await
+        .and_then(ExecutionReport::into_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triggers_on_chain_height_and_block_time() {
+        let status = NodeStatus {
+            chain_id: "cosmoshub-4".to_string(),
+            latest_height: 100,
+            catching_up: false,
+            latest_block_time: 1_700_000_000,
+        };
+
+        assert!(Trigger::Height(100).reached(&status));
+        assert!(!Trigger::Height(101).reached(&status));
+        assert!(Trigger::Time(1_699_999_999).reached(&status));
+        assert!(!Trigger::Time(1_700_000_001).reached(&status));
+
+        let parsed: Trigger = serde_json::from_str(r#"{"height": 12345}"#).unwrap();
+        assert_eq!(parsed, Trigger::Height(12345));
+    }
+}
@@ -0,0 +1,716 @@
+//! Snapshots of per-address holdings, used as the input for generating payments.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{self, File},
+    io::BufReader,
+};
+
+use eyre::{eyre, Result};
+use serde::{
+    de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use ocular::cosmrs::proto::cosmos::{
+    base::query::v1beta1::{PageRequest, PageResponse},
+    staking::v1beta1::{
+        query_client::QueryClient as StakingQueryClient, QueryValidatorDelegationsRequest,
+    },
+};
+
+use crate::{
+    grpc,
+    math::mul_div,
+    payments::Payment,
+    proto::{QueryDenomOwnersRequest, QueryDenomOwnersResponse},
+};
+
+/// Number of decimal places in a Cosmos SDK `Dec`
+const DEC_PRECISION: u32 = 18;
+
+/// Page size used when paginating live snapshot queries
+const PAGE_LIMIT: u64 = 1000;
+
+/// What a snapshot measures for each address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotRule {
+    /// Bank balance of a single denom
+    Balance { denom: String },
+    /// Tokens bonded to any validator, optionally including unbonding entries
+    Staked { include_unbonding: bool },
+}
+
+/// Holdings of a single address.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Holding {
+    pub address: String,
+    /// Stored as a string because the [`toml`] crate does not support [`u128`]
+    #[serde(with = "amount_string")]
+    pub amount: u128,
+}
+
+/// Per-address holdings at a point in a chain's history, sorted by address.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    pub chain_id: Option<String>,
+    /// Last committed height the holdings were taken at
+    pub height: Option<u64>,
+    pub holdings: Vec<Holding>,
+}
+
+impl Snapshot {
+    /// Builds a snapshot from an address -> amount map, dropping empty holdings.
+    pub fn from_holdings(
+        chain_id: Option<String>,
+        height: Option<u64>,
+        holdings: BTreeMap<String, u128>,
+    ) -> Snapshot {
+        Snapshot {
+            chain_id,
+            height,
+            holdings: holdings
+                .into_iter()
+                .filter(|(_, amount)| *amount > 0)
+                .map(|(address, amount)| Holding { address, amount })
+                .collect(),
+        }
+    }
+
+    /// Sum of all holdings. Returns an error if it doesn't fit in a `u128`.
+    pub fn total(&self) -> Result<u128> {
+        self.holdings.iter().try_fold(0u128, |total, h| {
+            total
+                .checked_add(h.amount)
+                .ok_or_else(|| eyre!("total of the snapshot's holdings overflows u128"))
+        })
+    }
+
+    /// Pays each address `amount * numerator / denominator` of `denom`, rounded down. Addresses
+    /// whose payment rounds to zero are skipped.
+    pub fn to_payments(
+        &self,
+        denom: &str,
+        numerator: u128,
+        denominator: u128,
+    ) -> Result<Vec<Payment>> {
+        if denominator == 0 {
+            return Err(eyre!("denominator must be greater than zero"));
+        }
+
+        let mut payments = Vec::<Payment>::new();
+        for h in &self.holdings {
+            let amount = mul_div(h.amount, numerator, denominator);
+            if amount == 0 {
+                continue;
+            }
+
+            payments.push(Payment {
+                recipient: h.address.clone(),
+                amount: u64::try_from(amount)
+                    .map_err(|_| eyre!("payment to {} exceeds u64::MAX", h.address))?,
+                denom: denom.to_string(),
+            });
+        }
+
+        Ok(payments)
+    }
+}
+
+/// Reads and deserializes a TOML file into a [`Snapshot`]
+pub fn read_snapshot_toml(path: &str) -> Result<Snapshot> {
+    let toml_string = fs::read_to_string(path)?;
+    Ok(toml::from_str(toml_string.as_str())?)
+}
+
+/// Serializes a snapshot into a toml at the specified path
+pub fn write_snapshot_toml(path: &str, snapshot: &Snapshot) -> Result<()> {
+    let toml_string = toml::to_string(snapshot)?;
+    Ok(fs::write(path, toml_string)?)
+}
+
+/// Takes a snapshot from an exported genesis file (e.g. the output of `gaiad export`). The file is
+/// streamed, so only the entries relevant to `rule` are held in memory.
+pub fn snapshot_from_genesis(path: &str, rule: &SnapshotRule) -> Result<Snapshot> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut collector = Collector::new(rule);
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    GenesisSeed(&mut collector).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+
+    collector.finish()
+}
+
+/// Takes a snapshot of everyone delegating to `validators` from a live node. Every page is
+/// queried at the same height so the snapshot is consistent; if `height` is `None` the node's
+/// latest height is used.
+pub async fn snapshot_delegators(
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+    validators: &[&str],
+    height: Option<u64>,
+) -> Result<Snapshot> {
+    let (chain_id, latest) = grpc::latest_height(rpc_endpoint).await?;
+    let height = height.unwrap_or(latest);
+    let mut client = StakingQueryClient::new(grpc::connect(grpc_endpoint).await?);
+    let mut holdings = BTreeMap::<String, u128>::new();
+    for validator in validators {
+        let mut key = Vec::<u8>::new();
+        loop {
+            let request = QueryValidatorDelegationsRequest {
+                validator_addr: validator.to_string(),
+                pagination: Some(page(key)),
+            };
+            let response = client
+                .validator_delegations(grpc::request(request, Some(height))?)
+                .await?
+                .into_inner();
+            for d in response.delegation_responses {
+                let delegator = d
+                    .delegation
+                    .ok_or_else(|| eyre!("delegation response missing delegation"))?
+                    .delegator_address;
+                let amount = match d.balance {
+                    Some(coin) => coin.amount.parse::<u128>()?,
+                    None => 0,
+                };
+                add_holding(&mut holdings, &delegator, amount)?;
+            }
+
+            key = next_key(response.pagination);
+            if key.is_empty() {
+                break;
+            }
+        }
+    }
+
+    Ok(Snapshot::from_holdings(
+        Some(chain_id),
+        Some(height),
+        holdings,
+    ))
+}
+
+/// Takes a snapshot of every holder of `denom` from a live node using the bank module's
+/// `DenomOwners` query (Cosmos SDK v0.46+). Pinned to `height` like [`snapshot_delegators`].
+pub async fn snapshot_denom_holders(
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+    denom: &str,
+    height: Option<u64>,
+) -> Result<Snapshot> {
+    let (chain_id, latest) = grpc::latest_height(rpc_endpoint).await?;
+    let height = height.unwrap_or(latest);
+    let channel = grpc::connect(grpc_endpoint).await?;
+    let mut holdings = BTreeMap::<String, u128>::new();
+    let mut key = Vec::<u8>::new();
+    loop {
+        let request = QueryDenomOwnersRequest {
+            denom: denom.to_string(),
+            pagination: Some(page(key)),
+        };
+        let response: QueryDenomOwnersResponse = grpc::unary(
+            channel.clone(),
+            "/cosmos.bank.v1beta1.Query/DenomOwners",
+            grpc::request(request, Some(height))?,
+        )
+        .await?;
+        for owner in response.denom_owners {
+            let amount = match owner.balance {
+                Some(coin) => coin.amount.parse::<u128>()?,
+                None => 0,
+            };
+            add_holding(&mut holdings, &owner.address, amount)?;
+        }
+
+        key = next_key(response.pagination);
+        if key.is_empty() {
+            break;
+        }
+    }
+
+    Ok(Snapshot::from_holdings(
+        Some(chain_id),
+        Some(height),
+        holdings,
+    ))
+}
+
+fn page(key: Vec<u8>) -> PageRequest {
+    PageRequest {
+        key,
+        limit: PAGE_LIMIT,
+        ..Default::default()
+    }
+}
+
+fn next_key(pagination: Option<PageResponse>) -> Vec<u8> {
+    pagination.map(|p| p.next_key).unwrap_or_default()
+}
+
+/// Adds `amount` to `address`'s holding, failing rather than wrapping on overflow.
+fn add_holding(holdings: &mut BTreeMap<String, u128>, address: &str, amount: u128) -> Result<()> {
+    let holding = holdings.entry(address.to_string()).or_insert(0);
+    *holding = holding
+        .checked_add(amount)
+        .ok_or_else(|| eyre!("holdings of {} overflow u128", address))?;
+
+    Ok(())
+}
+
+/// Parses a decimal string into an integer scaled by 10^18.
+pub(crate) fn parse_dec(value: &str) -> Result<u128> {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    if frac.len() > DEC_PRECISION as usize {
+        return Err(eyre!("too many decimal places in {}", value));
+    }
+
+    let int: u128 = int.parse()?;
+    let frac: u128 = if frac.is_empty() {
+        0
+    } else {
+        frac.parse::<u128>()? * 10u128.pow(DEC_PRECISION - frac.len() as u32)
+    };
+
+    int.checked_mul(10u128.pow(DEC_PRECISION))
+        .and_then(|i| i.checked_add(frac))
+        .ok_or_else(|| eyre!("decimal {} out of range", value))
+}
+
+/// Converts delegation shares into tokens given the validator's total tokens and shares.
+pub(crate) fn shares_to_tokens(
+    shares: u128,
+    validator_tokens: u128,
+    validator_shares: u128,
+) -> u128 {
+    if validator_shares == 0 {
+        return 0;
+    }
+
+    mul_div(shares, validator_tokens, validator_shares)
+}
+
+#[derive(Deserialize)]
+struct GenesisCoin {
+    denom: String,
+    amount: String,
+}
+
+#[derive(Deserialize)]
+struct GenesisBalance {
+    address: String,
+    coins: Vec<GenesisCoin>,
+}
+
+#[derive(Deserialize)]
+struct GenesisValidator {
+    operator_address: String,
+    tokens: String,
+    delegator_shares: String,
+}
+
+#[derive(Deserialize)]
+struct GenesisDelegation {
+    delegator_address: String,
+    validator_address: String,
+    shares: String,
+}
+
+#[derive(Deserialize)]
+struct GenesisUnbondingEntry {
+    balance: String,
+}
+
+#[derive(Deserialize)]
+struct GenesisUnbondingDelegation {
+    delegator_address: String,
+    entries: Vec<GenesisUnbondingEntry>,
+}
+
+/// Accumulates the entries of interest while the genesis file is streamed.
+struct Collector<'r> {
+    rule: &'r SnapshotRule,
+    chain_id: Option<String>,
+    initial_height: Option<u64>,
+    holdings: BTreeMap<String, u128>,
+    /// operator address -> (tokens, shares scaled by 10^18)
+    validators: HashMap<String, (u128, u128)>,
+    /// (delegator, validator, shares scaled by 10^18). Kept until the end because validators are
+    /// not guaranteed to precede delegations in the file.
+    delegations: Vec<(String, String, u128)>,
+    error: Option<eyre::Report>,
+}
+
+impl<'r> Collector<'r> {
+    fn new(rule: &'r SnapshotRule) -> Self {
+        Collector {
+            rule,
+            chain_id: None,
+            initial_height: None,
+            holdings: BTreeMap::new(),
+            validators: HashMap::new(),
+            delegations: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn add(&mut self, address: &str, amount: u128) {
+        let result = add_holding(&mut self.holdings, address, amount);
+        self.record(result);
+    }
+
+    fn record<T>(&mut self, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.error.get_or_insert(e);
+                None
+            }
+        }
+    }
+
+    fn balance(&mut self, balance: GenesisBalance) {
+        if let SnapshotRule::Balance { denom } = self.rule {
+            for coin in balance.coins.iter().filter(|c| &c.denom == denom) {
+                let amount = coin.amount.parse::<u128>().map_err(eyre::Report::from);
+                if let Some(amount) = self.record(amount) {
+                    self.add(&balance.address, amount);
+                }
+            }
+        }
+    }
+
+    fn validator(&mut self, validator: GenesisValidator) {
+        let tokens = validator.tokens.parse::<u128>().map_err(eyre::Report::from);
+        let shares = parse_dec(&validator.delegator_shares);
+        if let (Some(tokens), Some(shares)) = (self.record(tokens), self.record(shares)) {
+            self.validators
+                .insert(validator.operator_address, (tokens, shares));
+        }
+    }
+
+    fn delegation(&mut self, delegation: GenesisDelegation) {
+        if let SnapshotRule::Balance { .. } = self.rule {
+            return;
+        }
+
+        let shares = parse_dec(&delegation.shares);
+        if let Some(shares) = self.record(shares) {
+            self.delegations.push((
+                delegation.delegator_address,
+                delegation.validator_address,
+                shares,
+            ));
+        }
+    }
+
+    fn unbonding_delegation(&mut self, unbonding: GenesisUnbondingDelegation) {
+        if let SnapshotRule::Staked {
+            include_unbonding: true,
+        } = self.rule
+        {
+            for entry in &unbonding.entries {
+                let amount = entry.balance.parse::<u128>().map_err(eyre::Report::from);
+                if let Some(amount) = self.record(amount) {
+                    self.add(&unbonding.delegator_address, amount);
+                }
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Snapshot> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        for (delegator, validator, shares) in std::mem::take(&mut self.delegations) {
+            let (tokens, total_shares) = self
+                .validators
+                .get(&validator)
+                .copied()
+                .ok_or_else(|| eyre!("delegation to unknown validator {}", validator))?;
+            add_holding(
+                &mut self.holdings,
+                &delegator,
+                shares_to_tokens(shares, tokens, total_shares),
+            )?;
+        }
+
+        Ok(Snapshot::from_holdings(
+            self.chain_id,
+            self.initial_height.map(|h| h.saturating_sub(1)),
+            self.holdings,
+        ))
+    }
+}
+
+struct GenesisSeed<'a, 'r>(&'a mut Collector<'r>);
+
+impl<'de, 'a, 'r> DeserializeSeed<'de> for GenesisSeed<'a, 'r> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, 'r> Visitor<'de> for GenesisSeed<'a, 'r> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a genesis document")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let collector = self.0;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "chain_id" => collector.chain_id = Some(map.next_value()?),
+                "initial_height" => {
+                    let height: String = map.next_value()?;
+                    collector.initial_height = height.parse().ok();
+                }
+                "app_state" => map.next_value_seed(ModulesSeed(&mut *collector))?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Visits `app_state`, descending into the bank and staking module states.
+struct ModulesSeed<'a, 'r>(&'a mut Collector<'r>);
+
+impl<'de, 'a, 'r> DeserializeSeed<'de> for ModulesSeed<'a, 'r> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, 'r> Visitor<'de> for ModulesSeed<'a, 'r> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an app_state object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let collector = self.0;
+        while let Some(module) = map.next_key::<String>()? {
+            match module.as_str() {
+                "bank" | "staking" => map.next_value_seed(ModuleSeed(&mut *collector))?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Visits a module's genesis state, streaming the arrays the snapshot needs.
+struct ModuleSeed<'a, 'r>(&'a mut Collector<'r>);
+
+impl<'de, 'a, 'r> DeserializeSeed<'de> for ModuleSeed<'a, 'r> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, 'r> Visitor<'de> for ModuleSeed<'a, 'r> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a module genesis state")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let collector = self.0;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "balances" => {
+                    map.next_value_seed(EntriesSeed(&mut *collector, Collector::balance))?
+                }
+                "validators" => {
+                    map.next_value_seed(EntriesSeed(&mut *collector, Collector::validator))?
+                }
+                "delegations" => {
+                    map.next_value_seed(EntriesSeed(&mut *collector, Collector::delegation))?
+                }
+                "unbonding_delegations" => map.next_value_seed(EntriesSeed(
+                    &mut *collector,
+                    Collector::unbonding_delegation,
+                ))?,
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Streams an array, handing each element to the collector as soon as it is parsed.
+struct EntriesSeed<'a, 'r, T>(&'a mut Collector<'r>, fn(&mut Collector<'r>, T));
+
+impl<'de, 'a, 'r, T: Deserialize<'de>> DeserializeSeed<'de> for EntriesSeed<'a, 'r, T> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a, 'r, T: Deserialize<'de>> Visitor<'de> for EntriesSeed<'a, 'r, T> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array of genesis entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(entry) = seq.next_element::<T>()? {
+            (self.1)(self.0, entry);
+        }
+
+        Ok(())
+    }
+}
+
+mod amount_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(amount: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(amount)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS: &str = r#"{
+        "chain_id": "test-1",
+        "initial_height": "101",
+        "app_state": {
+            "auth": { "accounts": [] },
+            "bank": {
+                "balances": [
+                    { "address": "cosmos1a", "coins": [{ "denom": "uatom", "amount": "100" }] },
+                    { "address": "cosmos1b", "coins": [{ "denom": "stake", "amount": "5" }, { "denom": "uatom", "amount": "340282366920938463463374607431768211455" }] }
+                ]
+            },
+            "staking": {
+                "delegations": [
+                    { "delegator_address": "cosmos1a", "validator_address": "cosmosvaloper1x", "shares": "500.000000000000000000" },
+                    { "delegator_address": "cosmos1b", "validator_address": "cosmosvaloper1x", "shares": "1500.000000000000000000" }
+                ],
+                "validators": [
+                    { "operator_address": "cosmosvaloper1x", "tokens": "1800", "delegator_shares": "2000.000000000000000000", "jailed": false }
+                ],
+                "unbonding_delegations": [
+                    { "delegator_address": "cosmos1c", "validator_address": "cosmosvaloper1x", "entries": [{ "creation_height": "1", "balance": "7" }] }
+                ]
+            }
+        }
+    }"#;
+
+    fn write_genesis(name: &str) -> String {
+        let path = std::env::temp_dir()
+            .join(name)
+            .into_os_string()
+            .into_string()
+            .unwrap();
+        fs::write(&path, GENESIS).expect("failed to write genesis");
+
+        path
+    }
+
+    #[test]
+    fn snapshots_genesis_balances_and_stake() {
+        let path = write_genesis("snapshot_genesis_test.json");
+        let balances = snapshot_from_genesis(
+            &path,
+            &SnapshotRule::Balance {
+                denom: "uatom".to_string(),
+            },
+        )
+        .unwrap();
+        let staked = snapshot_from_genesis(
+            &path,
+            &SnapshotRule::Staked {
+                include_unbonding: true,
+            },
+        )
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(balances.chain_id.as_deref(), Some("test-1"));
+        assert_eq!(balances.height, Some(100));
+        assert_eq!(balances.holdings[0].amount, 100);
+        assert_eq!(balances.holdings[1].amount, u128::MAX);
+        assert_eq!(
+            staked.holdings,
+            vec![
+                Holding {
+                    address: "cosmos1a".to_string(),
+                    amount: 450
+                },
+                Holding {
+                    address: "cosmos1b".to_string(),
+                    amount: 1350
+                },
+                Holding {
+                    address: "cosmos1c".to_string(),
+                    amount: 7
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_and_reads_snapshot_toml() {
+        let snapshot = Snapshot::from_holdings(
+            Some("test-1".to_string()),
+            Some(100),
+            BTreeMap::from([
+                ("cosmos1a".to_string(), u128::MAX),
+                ("cosmos1b".to_string(), 0),
+            ]),
+        );
+        let path = std::env::temp_dir()
+            .join("snapshot_toml_test.toml")
+            .into_os_string()
+            .into_string()
+            .unwrap();
+        write_snapshot_toml(&path, &snapshot).unwrap();
+        let result = read_snapshot_toml(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(result, snapshot);
+        assert_eq!(result.holdings.len(), 1);
+        assert_eq!(result.total().unwrap(), u128::MAX);
+
+        let mut holdings = BTreeMap::from([("cosmos1a".to_string(), u128::MAX)]);
+        assert!(add_holding(&mut holdings, "cosmos1a", 1).is_err());
+        holdings.insert("cosmos1b".to_string(), 1);
+        assert!(Snapshot::from_holdings(None, None, holdings)
+            .total()
+            .is_err());
+    }
+}
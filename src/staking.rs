@@ -0,0 +1,484 @@
+//! Auto-staking of airdropped tokens on behalf of recipients who have authorized it.
+//!
+//! A recipient opts in by granting the campaign's account an authz authorization for
+//! `MsgDelegate`. After the airdrop, the campaign delegates a share of each opted-in recipient's
+//! allocation across a validator set with `MsgExec`, signing as the grantee.
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{eyre, Result};
+use ocular::{
+    chain::Context,
+    cosmrs::{
+        proto::cosmos::{
+            authz::v1beta1::Grant,
+            base::v1beta1::Coin,
+            staking::v1beta1::{stake_authorization::Policy, MsgDelegate, StakeAuthorization},
+        },
+        Any,
+    },
+    prelude::{AccountInfo, Authz},
+    tx::{FeeInfo, ModuleMsg},
+    QueryClient,
+};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    broadcast::TxConfirmation,
+    distribution::split_pool,
+    execute_airdrop,
+    executor::{ExecutionError, Executor},
+    math::mul_div,
+    payments::{batch_payments, Payment},
+    proto::to_any,
+};
+
+const MSG_DELEGATE_TYPE_URL: &str = "/cosmos.staking.v1beta1.MsgDelegate";
+const STAKE_AUTHORIZATION_TYPE_URL: &str = "/cosmos.staking.v1beta1.StakeAuthorization";
+
+/// A validator receiving a weighted portion of each auto-staked amount.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ValidatorWeight {
+    pub address: String,
+    pub weight: u64,
+}
+
+/// Which tokens are staked and where.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct AutoStake {
+    /// Bond denom of the chain; payments of other denoms are never staked
+    pub denom: String,
+    /// Each recipient stakes `allocation * numerator / denominator`, rounded down
+    pub numerator: u128,
+    pub denominator: u128,
+    pub validators: Vec<ValidatorWeight>,
+}
+
+/// A recipient that was not auto-staked, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Skipped {
+    pub recipient: String,
+    pub reason: String,
+}
+
+/// The outcome of auto-staking.
+#[derive(Debug, Default)]
+pub struct AutoStakeReport {
+    pub responses: Vec<TxConfirmation>,
+    /// Amount delegated per recipient
+    pub staked: BTreeMap<String, u128>,
+    pub skipped: Vec<Skipped>,
+}
+
+impl AutoStake {
+    /// The amount each recipient should stake, by recipient. Recipients whose share rounds to
+    /// zero are left out.
+    pub fn stake_amounts(&self, payments: &[Payment]) -> Result<BTreeMap<String, u128>> {
+        if self.denominator == 0 || self.numerator > self.denominator {
+            return Err(eyre!(
+                "auto-stake share {}/{} must be a fraction no greater than one",
+                self.numerator,
+                self.denominator
+            ));
+        }
+
+        let mut allocations = BTreeMap::<String, u128>::new();
+        for p in payments.iter().filter(|p| p.denom == self.denom) {
+            *allocations.entry(p.recipient.clone()).or_insert(0) += p.amount as u128;
+        }
+
+        Ok(allocations
+            .into_iter()
+            .map(|(recipient, amount)| {
+                let stake = mul_div(amount, self.numerator, self.denominator);
+                (recipient, stake)
+            })
+            .filter(|(_, stake)| *stake > 0)
+            .collect())
+    }
+
+    /// Splits `amount` across the validator set, skipping validators whose share is zero.
+    fn split(&self, amount: u128) -> Result<Vec<(&str, u128)>> {
+        let weights: Vec<u128> = self.validators.iter().map(|v| v.weight as u128).collect();
+
+        Ok(self
+            .validators
+            .iter()
+            .zip(split_pool(amount, &weights)?)
+            .filter(|(_, share)| *share > 0)
+            .map(|(v, share)| (v.address.as_str(), share))
+            .collect())
+    }
+
+    /// Builds the `MsgDelegate`s staking `amount` from `delegator` across the validator set.
+    pub fn delegate_msgs(&self, delegator: &str, amount: u128) -> Result<Vec<Any>> {
+        Ok(self
+            .split(amount)?
+            .into_iter()
+            .map(|(validator, share)| {
+                let msg = MsgDelegate {
+                    delegator_address: delegator.to_string(),
+                    validator_address: validator.to_string(),
+                    amount: Some(Coin {
+                        denom: self.denom.clone(),
+                        amount: share.to_string(),
+                    }),
+                };
+
+                to_any(MSG_DELEGATE_TYPE_URL, &msg)
+            })
+            .collect())
+    }
+
+    /// Checks whether any of a recipient's grants lets the campaign stake `amount` for it,
+    /// returning the reason it can't otherwise. Generic authorizations only need to be
+    /// unexpired; stake authorizations must also allow every validator and cover the amount.
+    pub fn check_grants(&self, grants: &[Grant], amount: u128, now: SystemTime) -> Result<()> {
+        let now = now.duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let mut reason = "no MsgDelegate grant".to_string();
+        for grant in grants {
+            if grant
+                .expiration
+                .as_ref()
+                .map(|e| e.seconds <= now)
+                .unwrap_or(false)
+            {
+                reason = "MsgDelegate grant has expired".to_string();
+                continue;
+            }
+
+            let authorization = match &grant.authorization {
+                Some(a) => a,
+                None => continue,
+            };
+            if authorization.type_url != STAKE_AUTHORIZATION_TYPE_URL {
+                return Ok(());
+            }
+
+            match self.check_stake_authorization(
+                &StakeAuthorization::decode(authorization.value.as_slice())?,
+                amount,
+            ) {
+                Ok(()) => return Ok(()),
+                Err(e) => reason = e.to_string(),
+            }
+        }
+
+        Err(eyre!(reason))
+    }
+
+    fn check_stake_authorization(&self, auth: &StakeAuthorization, amount: u128) -> Result<()> {
+        if let Some(max) = &auth.max_tokens {
+            if max.amount.parse::<u128>()? < amount {
+                return Err(eyre!(
+                    "stake authorization allows {}{}, {} needed",
+                    max.amount,
+                    max.denom,
+                    amount
+                ));
+            }
+        }
+
+        for (validator, _) in self.split(amount)? {
+            let allowed = match &auth.validators {
+                Some(Policy::AllowList(list)) => list.address.iter().any(|a| a == validator),
+                Some(Policy::DenyList(list)) => !list.address.iter().any(|a| a == validator),
+                None => true,
+            };
+            if !allowed {
+                return Err(eyre!(
+                    "stake authorization does not allow validator {}",
+                    validator
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether a grants query failed only because the granter has no grant of the queried type
+fn is_grant_not_found(message: &str) -> bool {
+    message.contains("authorization not found") || message.contains("no authorization found")
+}
+
+/// Stakes a share of each recipient's allocation in `payments` for every recipient that has
+/// authorized `grantee` to delegate for it. Recipients are staked in batches of at most
+/// `batch_size`, each recipient's delegations wrapped in its own `MsgExec`. Fails before
+/// broadcasting anything if a recipient's grants can't be queried.
+///
+/// Only recipients whose batch was included and succeeded are reported as staked. If sending
+/// stops part way, the recipients of the other batches are reported as skipped with the reason.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_auto_stake(
+    grantee: &AccountInfo,
+    payments: &[Payment],
+    auto_stake: &AutoStake,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<AutoStakeReport> {
+    let grantee_address = grantee.address(&chain_context.prefix)?;
+    let mut qclient = QueryClient::new(rpc_endpoint, grpc_endpoint)?;
+    let mut report = AutoStakeReport::default();
+    let mut stakeable = Vec::<(String, u128)>::new();
+    for (recipient, amount) in auto_stake.stake_amounts(payments)? {
+        let grants = match qclient
+            .grants(&recipient, &grantee_address, MSG_DELEGATE_TYPE_URL, None)
+            .await
+        {
+            Ok(response) => response.grants,
+            Err(e) if is_grant_not_found(&e.to_string()) => Vec::new(),
+            Err(e) => {
+                return Err(eyre!(
+                    "failed to query the MsgDelegate grants of {}: {}",
+                    recipient,
+                    e
+                ))
+            }
+        };
+
+        match auto_stake.check_grants(&grants, amount, SystemTime::now()) {
+            Ok(()) => stakeable.push((recipient, amount)),
+            Err(e) => report.skipped.push(Skipped {
+                recipient,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    let stake_batches = batch_payments(stakeable, batch_size);
+    let batches = stake_batches
+        .iter()
+        .map(|batch| {
+            batch
+                .iter()
+                .map(|(recipient, amount)| {
+                    Authz::Exec {
+                        grantee: &grantee_address,
+                        msgs: auto_stake.delegate_msgs(recipient, *amount)?,
+                    }
+                    .into_any()
+                })
+                .collect::<Result<Vec<Any>>>()
+        })
+        .collect::<Result<Vec<Vec<Any>>>>()?;
+    let executor = Executor::new(
+        grantee,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    );
+    let (execution, error) = match executor.execute(batches).await {
+        Ok(execution) => (execution, None),
+        Err(e) => match e.downcast::<ExecutionError>() {
+            Ok(failed) => (failed.report, Some(failed.error)),
+            Err(e) => return Err(e),
+        },
+    };
+
+    for (i, batch) in stake_batches.into_iter().enumerate() {
+        let reason = match execution.confirmation(i) {
+            Some(confirmation) if confirmation.is_ok() => {
+                report.staked.extend(batch);
+                continue;
+            }
+            Some(confirmation) => format!(
+                "transaction {} failed: {}",
+                confirmation.hash, confirmation.log
+            ),
+            None => match execution.pending.iter().find(|p| p.batch == i) {
+                Some(pending) => format!(
+                    "transaction {} was not confirmed and may still be included",
+                    pending.hash
+                ),
+                None => match &error {
+                    Some(e) => format!("not sent: {:#}", e),
+                    None => "not sent after an earlier batch failed".to_string(),
+                },
+            },
+        };
+        report
+            .skipped
+            .extend(batch.into_iter().map(|(recipient, _)| Skipped {
+                recipient,
+                reason: reason.clone(),
+            }));
+    }
+    report.responses = execution.confirmations;
+
+    Ok(report)
+}
+
+/// Runs [`execute_airdrop`] from `sender`, then auto-stakes for the recipients that have
+/// authorized it with [`execute_auto_stake`]. Once the airdrop is confirmed its confirmation is
+/// always returned, alongside the auto-stake outcome, so an auto-stake error can't hide that the
+/// airdrop was paid.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_airdrop_with_auto_stake(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    auto_stake: &AutoStake,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<(TxConfirmation, Result<AutoStakeReport>)> {
+    let response = execute_airdrop(
+        sender,
+        payments.clone(),
+        fee_info.clone(),
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await?;
+    let report = execute_auto_stake(
+        sender,
+        &payments,
+        auto_stake,
+        batch_size,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await;
+
+    Ok((response, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ocular::cosmrs::proto::cosmos::{
+        authz::v1beta1::GenericAuthorization, staking::v1beta1::stake_authorization::Validators,
+    };
+    use prost_types::Timestamp;
+
+    use super::*;
+
+    fn auto_stake() -> AutoStake {
+        AutoStake {
+            denom: "uatom".to_string(),
+            numerator: 1,
+            denominator: 2,
+            validators: vec![
+                ValidatorWeight {
+                    address: "cosmosvaloper1a".to_string(),
+                    weight: 1,
+                },
+                ValidatorWeight {
+                    address: "cosmosvaloper1b".to_string(),
+                    weight: 3,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn splits_stake_across_validators() {
+        let payments = vec![
+            Payment {
+                recipient: "alice".to_string(),
+                amount: 101,
+                denom: "uatom".to_string(),
+            },
+            Payment {
+                recipient: "bob".to_string(),
+                amount: 1,
+                denom: "uatom".to_string(),
+            },
+            Payment {
+                recipient: "carol".to_string(),
+                amount: 500,
+                denom: "uosmo".to_string(),
+            },
+        ];
+        let amounts = auto_stake().stake_amounts(&payments).unwrap();
+        let msgs = auto_stake()
+            .delegate_msgs("alice", amounts["alice"])
+            .unwrap();
+        let shares: Vec<String> = msgs
+            .iter()
+            .map(|m| {
+                MsgDelegate::decode(m.value.as_slice())
+                    .unwrap()
+                    .amount
+                    .unwrap()
+                    .amount
+            })
+            .collect();
+
+        assert_eq!(amounts.len(), 1);
+        assert_eq!(amounts["alice"], 50);
+        assert_eq!(shares, vec!["13", "37"]);
+    }
+
+    #[test]
+    fn checks_grants() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let stake_grant = |validators: Vec<String>, max: u128| Grant {
+            authorization: Some(to_any(
+                STAKE_AUTHORIZATION_TYPE_URL,
+                &StakeAuthorization {
+                    max_tokens: Some(Coin {
+                        denom: "uatom".to_string(),
+                        amount: max.to_string(),
+                    }),
+                    authorization_type: 1,
+                    validators: Some(Policy::AllowList(Validators {
+                        address: validators,
+                    })),
+                },
+            )),
+            expiration: None,
+        };
+        let both = vec!["cosmosvaloper1a".to_string(), "cosmosvaloper1b".to_string()];
+
+        assert!(auto_stake()
+            .check_grants(&[stake_grant(both.clone(), 50)], 50, now)
+            .is_ok());
+        assert!(auto_stake()
+            .check_grants(&[stake_grant(both, 49)], 50, now)
+            .is_err());
+        assert!(auto_stake()
+            .check_grants(
+                &[stake_grant(vec!["cosmosvaloper1a".to_string()], 50)],
+                50,
+                now
+            )
+            .is_err());
+
+        let expired = Grant {
+            authorization: Some(to_any(
+                "/cosmos.authz.v1beta1.GenericAuthorization",
+                &GenericAuthorization {
+                    msg: MSG_DELEGATE_TYPE_URL.to_string(),
+                },
+            )),
+            expiration: Some(Timestamp {
+                seconds: 999,
+                nanos: 0,
+            }),
+        };
+        assert!(auto_stake().check_grants(&[expired], 50, now).is_err());
+        assert!(auto_stake().check_grants(&[], 50, now).is_err());
+
+        assert!(is_grant_not_found(
+            "status: NotFound, message: \"authorization not found for \
+             /cosmos.staking.v1beta1.MsgDelegate type\""
+        ));
+        assert!(!is_grant_not_found(
+            "status: Unavailable, message: \"timeout\""
+        ));
+    }
+}
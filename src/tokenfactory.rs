@@ -0,0 +1,123 @@
+//! Airdrops of token-factory tokens that are minted in the same transaction that distributes them.
+//!
+//! The transaction mints exactly the totals of the `MsgMultiSend` input, so the supply minted for
+//! the airdrop always equals the supply distributed.
+use eyre::{eyre, Result};
+use ocular::{
+    chain::Context,
+    cosmrs::{bank::MultiSendIo, proto::cosmos::base::v1beta1::Coin, Any},
+    prelude::{AccountInfo, Bank},
+    tx::{FeeInfo, ModuleMsg},
+};
+
+use crate::{
+    broadcast::TxConfirmation,
+    multi_send_args_from_payments,
+    payments::Payment,
+    proto::{to_any, MsgMint},
+    sign_and_broadcast,
+};
+
+const MSG_MINT_TYPE_URL: &str = "/osmosis.tokenfactory.v1beta1.MsgMint";
+
+/// Denom prefix of tokens created with the token-factory module
+pub const FACTORY_PREFIX: &str = "factory/";
+
+/// Builds a `MsgMint` for every coin in `inputs`. Every coin must be a `factory/` denom, and the
+/// chain rejects the mint unless `sender_address` is the denom's admin.
+fn mint_msgs(sender_address: &str, inputs: &[MultiSendIo]) -> Result<Vec<Any>> {
+    inputs
+        .iter()
+        .flat_map(|io| io.coins.iter())
+        .map(|coin| {
+            let denom = coin.denom.to_string();
+            if !denom.starts_with(FACTORY_PREFIX) {
+                return Err(eyre!(
+                    "{} is not a token-factory denom and can't be minted",
+                    denom
+                ));
+            }
+
+            let msg = MsgMint {
+                sender: sender_address.to_string(),
+                amount: Some(Coin {
+                    denom,
+                    amount: coin.amount.to_string(),
+                }),
+            };
+
+            Ok(to_any(MSG_MINT_TYPE_URL, &msg))
+        })
+        .collect()
+}
+
+/// Builds the messages of a mint-and-distribute airdrop: a `MsgMint` per denom for the total
+/// paid out, followed by the `MsgMultiSend` distributing it.
+pub fn mint_and_send_msgs(sender_address: &str, payments: Vec<Payment>) -> Result<Vec<Any>> {
+    let (inputs, outputs) = multi_send_args_from_payments(sender_address, payments)?;
+    let mut msgs = mint_msgs(sender_address, &inputs)?;
+    msgs.push(Bank::MultiSend { inputs, outputs }.into_any()?);
+
+    Ok(msgs)
+}
+
+/// Mints the airdrop's totals and distributes them to the recipients in a single transaction
+/// signed by the denoms' admin.
+pub async fn execute_mint_airdrop(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<TxConfirmation> {
+    let address = sender.address(&chain_context.prefix)?;
+    let msgs = mint_and_send_msgs(&address, payments)?;
+    sign_and_broadcast(
+        sender,
+        msgs,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    const SENDER: &str = "osmo1n6j7gnld9yxfyh6tflxhjjmt404zruuap9zme4";
+
+    #[test]
+    fn mints_exactly_the_distributed_total() {
+        let denom = format!("{}{}/airdrop", FACTORY_PREFIX, SENDER);
+        let payments: Vec<Payment> = (1..=3)
+            .map(|i| Payment {
+                recipient: SENDER.to_string(),
+                amount: i * 100,
+                denom: denom.clone(),
+            })
+            .collect();
+        let msgs = mint_and_send_msgs(SENDER, payments.clone()).unwrap();
+        let mint = MsgMint::decode(msgs[0].value.as_slice()).unwrap();
+
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].type_url, MSG_MINT_TYPE_URL);
+        assert_eq!(msgs[1].type_url, "/cosmos.bank.v1beta1.MsgMultiSend");
+        assert_eq!(
+            mint.amount,
+            Some(Coin {
+                denom,
+                amount: "600".to_string(),
+            })
+        );
+
+        let mut native = payments;
+        native[0].denom = "uosmo".to_string();
+        assert!(mint_and_send_msgs(SENDER, native).is_err());
+    }
+}
@@ -0,0 +1,515 @@
+//! Cross-chain airdrops delivered as ICS-20 transfers.
+//!
+//! Payments whose recipient has the bech32 prefix of a configured [`IbcRoute`] are sent with
+//! `MsgTransfer` over the route's channel instead of `MsgMultiSend`. Routes through more than one
+//! chain rely on the packet-forward middleware and carry a `forward` memo for every extra hop.
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{eyre, Report, Result};
+use ocular::{
+    chain::Context,
+    cosmrs::{
+        proto::{
+            cosmos::base::v1beta1::Coin,
+            ibc::core::{
+                channel::v1::{
+                    query_client::QueryClient as ChannelQueryClient, QueryPacketCommitmentRequest,
+                },
+                client::v1::Height,
+            },
+        },
+        AccountId, Any,
+    },
+    prelude::AccountInfo,
+    tx::FeeInfo,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    address::convert_address,
+    broadcast::TxConfirmation,
+    execute_airdrop,
+    executor::ExecutionError,
+    ibc::resolve_denom,
+    payments::{batch_payments, Payment},
+    proto::{to_any, MsgTransfer},
+    sign_and_broadcast_batches,
+};
+
+const MSG_TRANSFER_TYPE_URL: &str = "/ibc.applications.transfer.v1.MsgTransfer";
+
+/// Relative timeout used when a route sets neither a timeout height nor timestamp
+pub const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+fn default_port() -> String {
+    "transfer".to_string()
+}
+
+/// A further hop taken by the packet-forward middleware after the first transfer lands.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ForwardHop {
+    /// Bech32 prefix of the chain this hop delivers to
+    pub prefix: String,
+    #[serde(default = "default_port")]
+    pub port: String,
+    /// Channel on the previous chain leading to this one
+    pub channel: String,
+    /// Forwarding timeout, e.g. `10m`, passed through to the middleware
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u8>,
+}
+
+/// How to reach recipients on a counterparty chain.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct IbcRoute {
+    /// Bech32 prefix of the chain at the other end of `channel`
+    pub prefix: String,
+    #[serde(default = "default_port")]
+    pub port: String,
+    /// Channel on the sending chain
+    pub channel: String,
+    /// Absolute timeout height on the counterparty chain
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_revision_number: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_height: Option<u64>,
+    /// Timeout relative to the time the messages are built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    /// Extra hops for multi-hop routes, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hops: Vec<ForwardHop>,
+}
+
+impl IbcRoute {
+    /// Bech32 prefix of the chain the recipients live on
+    pub fn destination_prefix(&self) -> &str {
+        match self.hops.last() {
+            Some(hop) => &hop.prefix,
+            None => &self.prefix,
+        }
+    }
+
+    fn timeout_height(&self) -> Option<Height> {
+        self.timeout_height.map(|revision_height| Height {
+            revision_number: self.timeout_revision_number.unwrap_or(0),
+            revision_height,
+        })
+    }
+
+    /// Timeout timestamp in nanoseconds since the epoch, or 0 for none. Falls back to
+    /// [`DEFAULT_TIMEOUT_SECS`] if the route has no timeout height either.
+    fn timeout_timestamp(&self, now: SystemTime) -> Result<u64> {
+        let secs = match (self.timeout_secs, self.timeout_height) {
+            (Some(secs), _) => secs,
+            (None, Some(_)) => return Ok(0),
+            (None, None) => DEFAULT_TIMEOUT_SECS,
+        };
+        let now = now.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+
+        Ok(now + secs * 1_000_000_000)
+    }
+
+    /// The packet-forward memo delivering to `recipient` through the route's hops, if it has any.
+    fn forward_memo(&self, recipient: &str) -> Result<String> {
+        let mut next: Option<serde_json::Value> = None;
+        for hop in self.hops.iter().rev() {
+            let mut forward = json!({
+                "receiver": convert_address(recipient, &hop.prefix)?,
+                "port": hop.port,
+                "channel": hop.channel,
+            });
+            if let Some(timeout) = &hop.timeout {
+                forward["timeout"] = json!(timeout);
+            }
+            if let Some(retries) = hop.retries {
+                forward["retries"] = json!(retries);
+            }
+            if let Some(next) = next {
+                forward["next"] = next;
+            }
+            next = Some(json!({ "forward": forward }));
+        }
+
+        Ok(next.map(|memo| memo.to_string()).unwrap_or_default())
+    }
+}
+
+/// Payments split by the chain their recipients live on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoutedPayments {
+    /// Payments to recipients on the sending chain
+    pub local: Vec<Payment>,
+    /// Payments to be transferred, by index of their route
+    pub remote: BTreeMap<usize, Vec<Payment>>,
+}
+
+/// Splits payments into those paid on the sending chain and those delivered over each route, by
+/// recipient prefix. Recipients matching neither are an error.
+pub fn split_payments_by_route(
+    local_prefix: &str,
+    payments: Vec<Payment>,
+    routes: &[IbcRoute],
+) -> Result<RoutedPayments> {
+    let mut local = Vec::<Payment>::new();
+    let mut remote = BTreeMap::<usize, Vec<Payment>>::new();
+    for p in payments {
+        let prefix = AccountId::from_str(&p.recipient)?.prefix().to_string();
+        if prefix == local_prefix {
+            local.push(p);
+            continue;
+        }
+
+        match routes.iter().position(|r| r.destination_prefix() == prefix) {
+            Some(i) => remote.entry(i).or_default().push(p),
+            None => {
+                return Err(eyre!(
+                    "no IBC route configured for recipient {} with prefix {}",
+                    p.recipient,
+                    prefix
+                ))
+            }
+        }
+    }
+
+    Ok(RoutedPayments { local, remote })
+}
+
+/// Builds one `MsgTransfer` per payment over `route`, timing out relative to `now`.
+pub fn transfer_msgs_from_payments(
+    sender_address: &str,
+    route: &IbcRoute,
+    payments: &[Payment],
+    now: SystemTime,
+) -> Result<Vec<Any>> {
+    let timeout_height = route.timeout_height();
+    let timeout_timestamp = route.timeout_timestamp(now)?;
+    payments
+        .iter()
+        .map(|p| {
+            if AccountId::from_str(&p.recipient)?.prefix() != route.destination_prefix() {
+                return Err(eyre!(
+                    "recipient {} is not on the route's destination chain {}",
+                    p.recipient,
+                    route.destination_prefix()
+                ));
+            }
+
+            let msg = MsgTransfer {
+                source_port: route.port.clone(),
+                source_channel: route.channel.clone(),
+                token: Some(Coin {
+                    denom: resolve_denom(&p.denom),
+                    amount: p.amount.to_string(),
+                }),
+                sender: sender_address.to_string(),
+                receiver: convert_address(&p.recipient, &route.prefix)?,
+                timeout_height: timeout_height.clone(),
+                timeout_timestamp,
+                memo: route.forward_memo(&p.recipient)?,
+            };
+
+            Ok(to_any(MSG_TRANSFER_TYPE_URL, &msg))
+        })
+        .collect()
+}
+
+/// A packet sent by an airdrop transfer, kept so its acknowledgement or timeout can be checked.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct SentPacket {
+    pub recipient: String,
+    pub port: String,
+    pub channel: String,
+    pub sequence: u64,
+    pub tx_hash: String,
+}
+
+/// Pairs the `send_packet` events of a transfer transaction with the payments it sent, in
+/// message order.
+pub fn sent_packets(
+    confirmation: &TxConfirmation,
+    payments: &[Payment],
+) -> Result<Vec<SentPacket>> {
+    if !confirmation.is_ok() {
+        return Err(eyre!(
+            "transfer transaction {} failed: {}",
+            confirmation.hash,
+            confirmation.log
+        ));
+    }
+
+    let packets = confirmation
+        .events
+        .iter()
+        .filter(|e| e.type_str == "send_packet")
+        .zip(payments)
+        .map(|(event, p)| {
+            let attribute = |key: &str| {
+                event
+                    .attributes
+                    .iter()
+                    .find(|t| t.key.as_ref() == key)
+                    .map(|t| t.value.to_string())
+                    .ok_or_else(|| eyre!("send_packet event is missing {}", key))
+            };
+
+            Ok(SentPacket {
+                recipient: p.recipient.clone(),
+                port: attribute("packet_src_port")?,
+                channel: attribute("packet_src_channel")?,
+                sequence: attribute("packet_sequence")?.parse()?,
+                tx_hash: confirmation.hash.clone(),
+            })
+        })
+        .collect::<Result<Vec<SentPacket>>>()?;
+
+    if packets.len() != payments.len() {
+        return Err(eyre!(
+            "transaction {} sent {} packets for {} transfers",
+            confirmation.hash,
+            packets.len(),
+            payments.len()
+        ));
+    }
+
+    Ok(packets)
+}
+
+/// Whether a sent packet is still awaiting an acknowledgement or timeout. The sending chain
+/// deletes a packet's commitment once either is processed.
+pub async fn packet_pending(grpc_endpoint: &str, packet: &SentPacket) -> Result<bool> {
+    let mut client = ChannelQueryClient::connect(grpc_endpoint.to_string()).await?;
+    let request = QueryPacketCommitmentRequest {
+        port_id: packet.port.clone(),
+        channel_id: packet.channel.clone(),
+        sequence: packet.sequence,
+    };
+
+    match client.packet_commitment(request).await {
+        Ok(response) => Ok(!response.into_inner().commitment.is_empty()),
+        Err(status) if status.code() == tonic::Code::NotFound => Ok(false),
+        Err(status) => Err(status.into()),
+    }
+}
+
+/// The transactions broadcast by a cross-chain airdrop and the packets they sent.
+#[derive(Debug, Default)]
+pub struct CrossChainAirdropResult {
+    /// The `MsgMultiSend` paying recipients on the sending chain, if there were any
+    pub local: Option<TxConfirmation>,
+    pub transfers: Vec<TxConfirmation>,
+    pub packets: Vec<SentPacket>,
+}
+
+/// Returned when a cross-chain airdrop stops after something was broadcast, along with what was
+/// sent so far. Recover it from an [`eyre::Report`] with
+/// `downcast_ref::<CrossChainAirdropError>()`.
+#[derive(Debug)]
+pub struct CrossChainAirdropError {
+    pub result: CrossChainAirdropResult,
+    pub error: Report,
+}
+
+impl fmt::Display for CrossChainAirdropError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#} (local payments {}, {} transfer transactions included)",
+            self.error,
+            if self.result.local.is_some() {
+                "sent"
+            } else {
+                "not sent"
+            },
+            self.result.transfers.len()
+        )
+    }
+}
+
+impl std::error::Error for CrossChainAirdropError {}
+
+/// Pays recipients on the sending chain with a single `MsgMultiSend` and everyone else with
+/// batches of at most `batch_size` transfers over their chain's route.
+///
+/// Every message is built before anything is broadcast. Errors after that are returned as a
+/// [`CrossChainAirdropError`] carrying the confirmations and packets so far.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_cross_chain_airdrop(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    routes: &[IbcRoute],
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<CrossChainAirdropResult> {
+    let address = sender.address(&chain_context.prefix)?;
+    let routed = split_payments_by_route(&chain_context.prefix, payments, routes)?;
+    let mut batches = Vec::<Vec<Payment>>::new();
+    let mut msgs = Vec::<Vec<Any>>::new();
+    for (i, route_payments) in routed.remote {
+        for batch in batch_payments(route_payments, batch_size) {
+            msgs.push(transfer_msgs_from_payments(
+                &address,
+                &routes[i],
+                &batch,
+                SystemTime::now(),
+            )?);
+            batches.push(batch);
+        }
+    }
+
+    let mut result = CrossChainAirdropResult::default();
+    if !routed.local.is_empty() {
+        result.local = Some(
+            execute_airdrop(
+                sender,
+                routed.local,
+                fee_info.clone(),
+                chain_context,
+                rpc_endpoint,
+                grpc_endpoint,
+            )
+            .await?,
+        );
+    }
+    if msgs.is_empty() {
+        return Ok(result);
+    }
+
+    let sent = sign_and_broadcast_batches(
+        sender,
+        msgs,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await;
+    // Pairs each included transaction with the payments in its batch
+    let (included, mut error) = match sent {
+        Ok(confirmations) => (confirmations.into_iter().zip(0..).collect(), None),
+        Err(e) => match e.downcast::<ExecutionError>() {
+            Ok(failed) => {
+                let report = failed.report;
+                let included = report.confirmations.into_iter().zip(report.batches);
+                (included.collect::<Vec<_>>(), Some(failed.error))
+            }
+            Err(e) if result.local.is_none() => return Err(e),
+            Err(e) => (Vec::new(), Some(e)),
+        },
+    };
+    for (response, batch) in included {
+        // A failed transaction sent no packets
+        if response.is_ok() {
+            match sent_packets(&response, &batches[batch]) {
+                Ok(packets) => result.packets.extend(packets),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        result.transfers.push(response);
+    }
+
+    match error {
+        Some(error) => Err(CrossChainAirdropError { result, error }.into()),
+        None => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use prost::Message;
+
+    use super::*;
+
+    const SENDER: &str = "cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08";
+    const OSMO: &str = "osmo1n6j7gnld9yxfyh6tflxhjjmt404zruuap9zme4";
+    const JUNO: &str = "juno1n6j7gnld9yxfyh6tflxhjjmt404zruualvjsgm";
+
+    fn payment(recipient: &str) -> Payment {
+        Payment {
+            recipient: recipient.to_string(),
+            amount: 10,
+            denom: "uatom".to_string(),
+        }
+    }
+
+    fn routes() -> Vec<IbcRoute> {
+        let osmo: IbcRoute =
+            toml::from_str("prefix = \"osmo\"\nchannel = \"channel-141\"").unwrap();
+        let mut juno = osmo.clone();
+        juno.hops.push(ForwardHop {
+            prefix: "juno".to_string(),
+            port: "transfer".to_string(),
+            channel: "channel-42".to_string(),
+            timeout: Some("10m".to_string()),
+            retries: None,
+        });
+
+        vec![osmo, juno]
+    }
+
+    #[test]
+    fn splits_payments_by_route() {
+        let payments = vec![payment(SENDER), payment(JUNO), payment(OSMO)];
+        let routed = split_payments_by_route("cosmos", payments, &routes()).unwrap();
+
+        assert_eq!(routed.local, vec![payment(SENDER)]);
+        assert_eq!(routed.remote[&0], vec![payment(OSMO)]);
+        assert_eq!(routed.remote[&1], vec![payment(JUNO)]);
+        assert!(split_payments_by_route(
+            "cosmos",
+            vec![payment("evmos1n6j7gnld9yxfyh6tflxhjjmt404zruuatlq940")],
+            &routes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn builds_transfers_with_forward_memo() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let routes = routes();
+        let msgs = transfer_msgs_from_payments(SENDER, &routes[1], &[payment(JUNO)], now).unwrap();
+        let msg = MsgTransfer::decode(msgs[0].value.as_slice()).unwrap();
+        let memo: serde_json::Value = serde_json::from_str(&msg.memo).unwrap();
+
+        assert_eq!(msgs[0].type_url, MSG_TRANSFER_TYPE_URL);
+        assert_eq!(msg.source_channel, "channel-141");
+        assert_eq!(msg.receiver, OSMO);
+        assert_eq!(
+            msg.timeout_timestamp,
+            (1_000 + DEFAULT_TIMEOUT_SECS) * 1_000_000_000
+        );
+        assert_eq!(
+            memo,
+            json!({ "forward": {
+                "receiver": JUNO,
+                "port": "transfer",
+                "channel": "channel-42",
+                "timeout": "10m",
+            }})
+        );
+
+        let direct =
+            transfer_msgs_from_payments(SENDER, &routes[0], &[payment(OSMO)], now).unwrap();
+
+        assert!(MsgTransfer::decode(direct[0].value.as_slice())
+            .unwrap()
+            .memo
+            .is_empty());
+        assert!(transfer_msgs_from_payments(SENDER, &routes[0], &[payment(JUNO)], now).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+//! Airdrops that vest, by creating a vesting account for every recipient.
+//!
+//! A vesting account can only be created for an address the chain has never seen, so all of a
+//! recipient's payments are merged into a single message and recipients with existing accounts
+//! are rejected before anything is broadcast.
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use eyre::{eyre, Result};
+use ocular::{
+    chain::Context,
+    cosmrs::{proto::cosmos::base::v1beta1::Coin, AccountId, Any},
+    prelude::AccountInfo,
+    tx::FeeInfo,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    broadcast::TxConfirmation,
+    distribution::split_pool,
+    grpc,
+    ibc::resolve_denom,
+    payments::{batch_payments, Payment},
+    proto::{to_any, MsgCreatePeriodicVestingAccount, MsgCreateVestingAccount, VestingPeriod},
+    sign_and_broadcast_batches,
+};
+
+const MSG_CREATE_VESTING_ACCOUNT_TYPE_URL: &str = "/cosmos.vesting.v1beta1.MsgCreateVestingAccount";
+const MSG_CREATE_PERIODIC_VESTING_ACCOUNT_TYPE_URL: &str =
+    "/cosmos.vesting.v1beta1.MsgCreatePeriodicVestingAccount";
+
+/// A stretch of a periodic schedule. Each recipient's allocation is split across the periods in
+/// proportion to their weights.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Period {
+    /// Length in seconds
+    pub length: i64,
+    pub weight: u64,
+}
+
+/// How the airdropped tokens unlock. Times are Unix timestamps in seconds.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VestingSchedule {
+    /// Unlocks linearly from the time of the block that creates the account until `end_time`.
+    /// `MsgCreateVestingAccount` has no start time, so the start can't be scheduled.
+    Continuous { end_time: i64 },
+    /// Unlocks entirely at `end_time`
+    Delayed { end_time: i64 },
+    /// Unlocks each period's share when the period ends
+    Periodic {
+        start_time: i64,
+        periods: Vec<Period>,
+    },
+}
+
+impl VestingSchedule {
+    /// Checks that the schedule describes a non-empty span of time.
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            VestingSchedule::Continuous { end_time } | VestingSchedule::Delayed { end_time }
+                if *end_time <= 0 =>
+            {
+                Err(eyre!("invalid vesting end time {}", end_time))
+            }
+            VestingSchedule::Periodic { periods, .. } => {
+                if periods.is_empty() {
+                    return Err(eyre!("periodic vesting schedule has no periods"));
+                }
+                if periods.iter().any(|p| p.length <= 0) {
+                    return Err(eyre!("vesting periods must have a positive length"));
+                }
+                if periods.iter().all(|p| p.weight == 0) {
+                    return Err(eyre!("vesting periods must not all have zero weight"));
+                }
+
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Merges payments into the coins each recipient receives, in order of first appearance.
+fn coins_by_recipient(payments: &[Payment]) -> Vec<(String, BTreeMap<String, u128>)> {
+    let mut recipients = Vec::<(String, BTreeMap<String, u128>)>::new();
+    let mut indices = HashMap::<&str, usize>::new();
+    for p in payments {
+        let index = *indices.entry(p.recipient.as_str()).or_insert_with(|| {
+            recipients.push((p.recipient.clone(), BTreeMap::new()));
+            recipients.len() - 1
+        });
+        *recipients[index]
+            .1
+            .entry(resolve_denom(&p.denom))
+            .or_insert(0) += p.amount as u128;
+    }
+
+    recipients
+}
+
+fn to_coins(coins: &BTreeMap<String, u128>) -> Vec<Coin> {
+    coins
+        .iter()
+        .map(|(denom, amount)| Coin {
+            denom: denom.clone(),
+            amount: amount.to_string(),
+        })
+        .collect()
+}
+
+/// Builds one vesting account creation message per recipient following `schedule`. Every
+/// recipient must share the sender's address prefix.
+pub fn vesting_msgs_from_payments(
+    sender_address: &str,
+    payments: &[Payment],
+    schedule: &VestingSchedule,
+) -> Result<Vec<Any>> {
+    schedule.validate()?;
+    let sender = AccountId::from_str(sender_address)?;
+    coins_by_recipient(payments)
+        .into_iter()
+        .map(|(recipient, coins)| {
+            if AccountId::from_str(&recipient)?.prefix() != sender.prefix() {
+                return Err(eyre!(
+                    "recipient {} does not match the sender's address prefix {}",
+                    recipient,
+                    sender.prefix()
+                ));
+            }
+
+            let msg = match schedule {
+                VestingSchedule::Continuous { end_time } => to_any(
+                    MSG_CREATE_VESTING_ACCOUNT_TYPE_URL,
+                    &MsgCreateVestingAccount {
+                        from_address: sender_address.to_string(),
+                        to_address: recipient,
+                        amount: to_coins(&coins),
+                        end_time: *end_time,
+                        delayed: false,
+                    },
+                ),
+                VestingSchedule::Delayed { end_time } => to_any(
+                    MSG_CREATE_VESTING_ACCOUNT_TYPE_URL,
+                    &MsgCreateVestingAccount {
+                        from_address: sender_address.to_string(),
+                        to_address: recipient,
+                        amount: to_coins(&coins),
+                        end_time: *end_time,
+                        delayed: true,
+                    },
+                ),
+                VestingSchedule::Periodic {
+                    start_time,
+                    periods,
+                } => {
+                    let weights: Vec<u128> = periods.iter().map(|p| p.weight as u128).collect();
+                    let mut period_coins = vec![BTreeMap::<String, u128>::new(); periods.len()];
+                    for (denom, amount) in &coins {
+                        for (i, share) in split_pool(*amount, &weights)?.into_iter().enumerate() {
+                            if share > 0 {
+                                period_coins[i].insert(denom.clone(), share);
+                            }
+                        }
+                    }
+
+                    to_any(
+                        MSG_CREATE_PERIODIC_VESTING_ACCOUNT_TYPE_URL,
+                        &MsgCreatePeriodicVestingAccount {
+                            from_address: sender_address.to_string(),
+                            to_address: recipient,
+                            start_time: *start_time,
+                            vesting_periods: periods
+                                .iter()
+                                .zip(&period_coins)
+                                .map(|(p, c)| VestingPeriod {
+                                    length: p.length,
+                                    amount: to_coins(c),
+                                })
+                                .collect(),
+                        },
+                    )
+                }
+            };
+
+            Ok(msg)
+        })
+        .collect()
+}
+
+/// Checks that none of the recipients in `payments` have an account on chain yet.
+pub async fn check_recipients_are_new(grpc_endpoint: &str, payments: &[Payment]) -> Result<()> {
+    let mut existing = Vec::<String>::new();
+    for (recipient, _) in coins_by_recipient(payments) {
+        if grpc::account(grpc_endpoint, &recipient).await?.is_some() {
+            existing.push(recipient);
+        }
+    }
+
+    if !existing.is_empty() {
+        return Err(eyre!(
+            "vesting accounts can't be created for existing accounts: {}",
+            existing.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Creates a vesting account for every recipient following `schedule`, with at most
+/// `batch_size` accounts created per transaction.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_vesting_airdrop(
+    sender: &AccountInfo,
+    payments: Vec<Payment>,
+    schedule: &VestingSchedule,
+    batch_size: usize,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Vec<TxConfirmation>> {
+    let address = sender.address(&chain_context.prefix)?;
+    let msgs = vesting_msgs_from_payments(&address, &payments, schedule)?;
+    check_recipients_are_new(grpc_endpoint, &payments).await?;
+
+    sign_and_broadcast_batches(
+        sender,
+        batch_payments(msgs, batch_size),
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    const SENDER: &str = "cosmos1n6j7gnld9yxfyh6tflxhjjmt404zruuaf73t08";
+
+    fn payments() -> Vec<Payment> {
+        vec![
+            Payment {
+                recipient: SENDER.to_string(),
+                amount: 100,
+                denom: "uatom".to_string(),
+            },
+            Payment {
+                recipient: SENDER.to_string(),
+                amount: 1,
+                denom: "uatom".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn builds_one_continuous_vesting_msg_per_recipient() {
+        let schedule = VestingSchedule::Continuous { end_time: 2_000 };
+        let msgs = vesting_msgs_from_payments(SENDER, &payments(), &schedule).unwrap();
+        let msg = MsgCreateVestingAccount::decode(msgs[0].value.as_slice()).unwrap();
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].type_url, MSG_CREATE_VESTING_ACCOUNT_TYPE_URL);
+        assert_eq!(msg.amount[0].amount, "101");
+        assert!(!msg.delayed);
+
+        let unset = VestingSchedule::Continuous { end_time: 0 };
+        assert!(vesting_msgs_from_payments(SENDER, &payments(), &unset).is_err());
+    }
+
+    #[test]
+    fn splits_periodic_allocations_by_weight() {
+        let schedule: VestingSchedule = toml::from_str(
+            r#"
+            type = "periodic"
+            start_time = 1000
+            periods = [{ length = 60, weight = 1 }, { length = 60, weight = 2 }]
+            "#,
+        )
+        .unwrap();
+        let msgs = vesting_msgs_from_payments(SENDER, &payments(), &schedule).unwrap();
+        let msg = MsgCreatePeriodicVestingAccount::decode(msgs[0].value.as_slice()).unwrap();
+        let amounts: Vec<&str> = msg
+            .vesting_periods
+            .iter()
+            .map(|p| p.amount[0].amount.as_str())
+            .collect();
+
+        assert_eq!(
+            msgs[0].type_url,
+            MSG_CREATE_PERIODIC_VESTING_ACCOUNT_TYPE_URL
+        );
+        assert_eq!(msg.start_time, 1_000);
+        assert_eq!(amounts, vec!["34", "67"]);
+    }
+}
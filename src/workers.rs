@@ -0,0 +1,664 @@
+//! Fan-out airdrops distributed in parallel by worker accounts.
+//!
+//! A single account can only advance one sequence at a time, which caps how many transactions it
+//! gets into each block. In fan-out mode the treasury funds a set of worker accounts, derived
+//! deterministically from a mnemonic, with exactly their share of the payments plus fees in one
+//! `MsgMultiSend`. Each worker then distributes its shard of the payments with its own
+//! [`Executor`], and finally sweeps whatever it has left back to the treasury. Every step is
+//! recorded in a [`Journal`], which [`recover_fan_out_airdrop`] reads to sweep the workers back
+//! after an interrupted run.
+use std::{collections::BTreeMap, str::FromStr};
+
+use eyre::{eyre, Result};
+use futures::future::join_all;
+use ocular::{
+    chain::Context,
+    cosmrs::{
+        bank::MsgSend,
+        bip32::{Language, Mnemonic},
+        crypto::secp256k1::SigningKey,
+        proto::cosmos::base::v1beta1::Coin as ProtoCoin,
+        tx::Msg,
+        AccountId, Coin, Denom,
+    },
+    prelude::AccountInfo,
+    tx::FeeInfo,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    broadcast::{wait_for_tx, ConfirmationOptions, TxConfirmation},
+    executor::{ExecutionError, ExecutionReport, Executor, PendingTx},
+    grpc,
+    ibc::resolve_denom,
+    journal::Journal,
+    multi_send_batches, multi_send_from_payments,
+    payments::Payment,
+    sign_and_broadcast,
+};
+
+/// SLIP-44 coin type used by most Cosmos SDK chains
+pub const COSMOS_COIN_TYPE: u32 = 118;
+
+/// How to fan an airdrop out over worker accounts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FanOut {
+    /// 24 word BIP-39 mnemonic the workers are derived from
+    pub mnemonic: String,
+    pub workers: u32,
+    /// HD index of the first worker, at `m/44'/{coin_type}'/0'/0/{index}`
+    pub first_index: u32,
+    pub coin_type: u32,
+    /// Payments per transaction sent by each worker
+    pub batch_size: usize,
+}
+
+impl FanOut {
+    pub fn new(mnemonic: &str, workers: u32, batch_size: usize) -> Self {
+        FanOut {
+            mnemonic: mnemonic.to_string(),
+            workers,
+            first_index: 0,
+            coin_type: COSMOS_COIN_TYPE,
+            batch_size,
+        }
+    }
+
+    /// Derives the worker accounts, in index order.
+    pub fn derive_workers(&self) -> Result<Vec<AccountInfo>> {
+        let phrase = self
+            .mnemonic
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let seed = Mnemonic::new(phrase, Language::English)?.to_seed("");
+        (self.first_index..self.first_index + self.workers)
+            .map(|i| {
+                let path = format!("m/44'/{}'/0'/0/{}", self.coin_type, i);
+                let key = SigningKey::derive_from_path(seed.as_bytes(), &path.parse()?)?;
+                Ok(AccountInfo::from(key))
+            })
+            .collect()
+    }
+}
+
+/// Splits payments into `shards` contiguous shards whose sizes differ by at most one.
+pub fn shard_payments(payments: Vec<Payment>, shards: usize) -> Vec<Vec<Payment>> {
+    let shards = shards.max(1);
+    let (size, remainder) = (payments.len() / shards, payments.len() % shards);
+    let mut payments = payments.into_iter();
+
+    (0..shards)
+        .map(|i| {
+            let take = size + usize::from(i < remainder);
+            payments.by_ref().take(take).collect()
+        })
+        .collect()
+}
+
+/// Builds the treasury's payments to the workers: each worker's shard totals plus the fee of one
+/// transaction per batch and of the final sweep. Workers with empty shards aren't funded.
+pub fn funding_payments(
+    workers: &[String],
+    shards: &[Vec<Payment>],
+    fee: &Coin,
+    batch_size: usize,
+) -> Result<Vec<Payment>> {
+    let mut funding = Vec::<Payment>::new();
+    for (worker, shard) in workers.iter().zip(shards) {
+        if shard.is_empty() {
+            continue;
+        }
+
+        let mut totals = BTreeMap::<String, u128>::new();
+        for p in shard {
+            *totals.entry(resolve_denom(&p.denom)).or_insert(0) += p.amount as u128;
+        }
+        // one transaction per batch, plus the sweep
+        let transactions = (shard.len() - 1) / batch_size.max(1) + 2;
+        *totals.entry(fee.denom.to_string()).or_insert(0) += fee.amount * transactions as u128;
+
+        for (denom, total) in totals {
+            funding.push(Payment {
+                recipient: worker.clone(),
+                amount: u64::try_from(total)
+                    .map_err(|_| eyre!("worker {} would receive {} {}", worker, total, denom))?,
+                denom,
+            });
+        }
+    }
+
+    Ok(funding)
+}
+
+/// The coins a worker sweeps back: its whole balance less the sweep's fee.
+pub fn sweep_coins(balances: &[ProtoCoin], fee: &Coin) -> Result<Vec<Coin>> {
+    let mut coins = Vec::<Coin>::new();
+    for balance in balances {
+        let mut amount: u128 = balance.amount.parse()?;
+        if balance.denom == fee.denom.to_string() {
+            amount = amount.saturating_sub(fee.amount);
+        }
+
+        if amount > 0 {
+            coins.push(Coin {
+                denom: Denom::from_str(&balance.denom)?,
+                amount,
+            });
+        }
+    }
+
+    Ok(coins)
+}
+
+/// A step of a fan-out airdrop, as recorded in its journal.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum FanOutStep {
+    Derived {
+        workers: Vec<String>,
+    },
+    Funded {
+        tx_hash: String,
+    },
+    Distributed {
+        worker: String,
+        tx_hashes: Vec<String>,
+    },
+    DistributionFailed {
+        worker: String,
+        error: String,
+    },
+    Swept {
+        worker: String,
+        tx_hash: String,
+    },
+    SweepFailed {
+        worker: String,
+        error: String,
+    },
+}
+
+/// What one worker did.
+#[derive(Clone, Debug, Default)]
+pub struct WorkerReport {
+    pub address: String,
+    pub payment_count: usize,
+    pub execution: Option<ExecutionReport>,
+    /// Why the worker didn't distribute its whole shard
+    pub error: Option<String>,
+    pub sweep: Option<TxConfirmation>,
+    /// Why the worker wasn't swept, if it should have been
+    pub sweep_error: Option<String>,
+    /// Steps that couldn't be written to the journal
+    pub journal_errors: Vec<String>,
+}
+
+impl WorkerReport {
+    /// Records `step` in `journal`, keeping the error in the report if it can't be written.
+    fn record(&mut self, journal: &Journal, step: FanOutStep) {
+        if let Err(e) = journal.record(step) {
+            self.journal_errors.push(format!("{:#}", e));
+        }
+    }
+}
+
+/// The outcome of [`execute_fan_out_airdrop`].
+#[derive(Clone, Debug)]
+pub struct FanOutReport {
+    pub funding: TxConfirmation,
+    pub workers: Vec<WorkerReport>,
+    /// Steps of the run itself, rather than of a worker, that couldn't be written to the journal
+    pub journal_errors: Vec<String>,
+}
+
+/// Funds the workers from `treasury`, distributes their shards of `payments` in parallel and
+/// sweeps their leftover balances back to `treasury`, journaling every step.
+///
+/// A worker that fails part way still sweeps what it has left once its transactions have
+/// settled; its error is in its report. Refuses to run if `journal` already records workers,
+/// since they may have been funded and would be funded twice; use [`recover_fan_out_airdrop`] to
+/// sweep them instead. Once the funding is confirmed, journal errors no longer stop the run and
+/// are kept in the report.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_fan_out_airdrop(
+    treasury: &AccountInfo,
+    payments: Vec<Payment>,
+    fan_out: &FanOut,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+    journal: &Journal,
+) -> Result<FanOutReport> {
+    let steps: Vec<FanOutStep> = journal
+        .entries::<FanOutStep>()?
+        .into_iter()
+        .map(|entry| entry.step)
+        .collect();
+    let funded = steps.iter().find_map(|step| match step {
+        FanOutStep::Funded { tx_hash } => Some(tx_hash),
+        _ => None,
+    });
+    if let Some(tx_hash) = funded {
+        return Err(eyre!(
+            "the journal already records funding transaction {}; recover the workers with \
+             recover_fan_out_airdrop or start a new journal",
+            tx_hash
+        ));
+    }
+    // The funding may have been sent without being journaled
+    if steps
+        .iter()
+        .any(|step| matches!(step, FanOutStep::Derived { .. }))
+    {
+        return Err(eyre!(
+            "the journal already records workers that may have been funded; check the treasury's \
+             transactions, then recover the workers with recover_fan_out_airdrop or start a new \
+             journal"
+        ));
+    }
+
+    let treasury_address = treasury.address(&chain_context.prefix)?;
+    let workers = fan_out.derive_workers()?;
+    let addresses = workers
+        .iter()
+        .map(|w| w.address(&chain_context.prefix))
+        .collect::<Result<Vec<String>>>()?;
+    journal.record(FanOutStep::Derived {
+        workers: addresses.clone(),
+    })?;
+
+    let shards = shard_payments(payments, workers.len());
+    let fee = fee_info.get_fee();
+    let funding = funding_payments(&addresses, &shards, &fee, fan_out.batch_size)?;
+    let funding = sign_and_broadcast(
+        treasury,
+        vec![multi_send_from_payments(&treasury_address, funding)?],
+        fee_info.clone(),
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await?;
+    let mut journal_errors = Vec::<String>::new();
+    if let Err(e) = journal.record(FanOutStep::Funded {
+        tx_hash: funding.hash.clone(),
+    }) {
+        journal_errors.push(format!("{:#}", e));
+    }
+
+    let runs = workers
+        .iter()
+        .zip(&addresses)
+        .zip(shards)
+        .map(|((worker, address), shard)| {
+            let fee_info = fee_info.clone();
+            let treasury_address = &treasury_address;
+            async move {
+                let mut report = WorkerReport {
+                    address: address.clone(),
+                    payment_count: shard.len(),
+                    ..Default::default()
+                };
+                if shard.is_empty() {
+                    return report;
+                }
+
+                let execution = match multi_send_batches(address, shard, fan_out.batch_size) {
+                    Ok(batches) => {
+                        let executor = Executor::new(
+                            worker,
+                            fee_info.clone(),
+                            chain_context,
+                            rpc_endpoint,
+                            grpc_endpoint,
+                        );
+                        match executor
+                            .execute(batches)
+                            .await
+                            .and_then(ExecutionReport::into_result)
+                        {
+                            Ok(execution) => Some(execution),
+                            Err(e) => {
+                                report.error = Some(format!("{:#}", e));
+                                e.downcast::<ExecutionError>().ok().map(|f| f.report)
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        report.error = Some(format!("{:#}", e));
+                        None
+                    }
+                };
+                if let Some(execution) = &execution {
+                    let tx_hashes = execution
+                        .confirmations
+                        .iter()
+                        .map(|c| c.hash.clone())
+                        .collect();
+                    report.record(
+                        journal,
+                        FanOutStep::Distributed {
+                            worker: address.clone(),
+                            tx_hashes,
+                        },
+                    );
+                }
+                if let Some(error) = report.error.clone() {
+                    report.record(
+                        journal,
+                        FanOutStep::DistributionFailed {
+                            worker: address.clone(),
+                            error,
+                        },
+                    );
+                }
+
+                // Transactions that may still land would race the sweep's sequence and balance
+                let pending = execution
+                    .as_ref()
+                    .map(|e| e.pending.clone())
+                    .unwrap_or_default();
+                report.execution = execution;
+                let unsettled = unsettled_hashes(rpc_endpoint, &pending).await;
+                if unsettled.is_empty() {
+                    sweep_worker(
+                        worker,
+                        treasury_address,
+                        fee_info,
+                        chain_context,
+                        rpc_endpoint,
+                        grpc_endpoint,
+                        journal,
+                        &mut report,
+                    )
+                    .await;
+                } else {
+                    let error = format!(
+                        "transactions {} may still be included; sweep the worker with \
+                         recover_fan_out_airdrop once they have settled",
+                        unsettled.join(", ")
+                    );
+                    report.record(
+                        journal,
+                        FanOutStep::SweepFailed {
+                            worker: address.clone(),
+                            error: error.clone(),
+                        },
+                    );
+                    report.sweep_error = Some(error);
+                }
+
+                report
+            }
+        });
+    let workers = join_all(runs).await;
+
+    Ok(FanOutReport {
+        funding,
+        workers,
+        journal_errors,
+    })
+}
+
+/// Sweeps the workers of an interrupted [`execute_fan_out_airdrop`] back to `treasury_address`.
+/// The workers are taken from `journal`, and those it records as swept are skipped. Nothing is
+/// funded or distributed.
+///
+/// Run it once the interrupted run's transactions have had time to be included or to expire, so
+/// the sweeps don't race them.
+#[allow(clippy::too_many_arguments)]
+pub async fn recover_fan_out_airdrop(
+    treasury_address: &str,
+    fan_out: &FanOut,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+    journal: &Journal,
+) -> Result<Vec<WorkerReport>> {
+    let steps: Vec<FanOutStep> = journal
+        .entries::<FanOutStep>()?
+        .into_iter()
+        .map(|entry| entry.step)
+        .collect();
+    let to_sweep = workers_to_recover(&steps)?;
+    let mut workers = Vec::<(AccountInfo, String)>::new();
+    for worker in fan_out.derive_workers()? {
+        let address = worker.address(&chain_context.prefix)?;
+        workers.push((worker, address));
+    }
+    if let Some(unknown) = to_sweep
+        .iter()
+        .find(|w| !workers.iter().any(|(_, address)| address == *w))
+    {
+        return Err(eyre!(
+            "the fan-out doesn't derive worker {} from the journal; check the mnemonic and indices",
+            unknown
+        ));
+    }
+
+    let mut reports = Vec::<WorkerReport>::new();
+    for (worker, address) in workers {
+        if !to_sweep.contains(&address) {
+            continue;
+        }
+
+        let mut report = WorkerReport {
+            address,
+            ..Default::default()
+        };
+        sweep_worker(
+            &worker,
+            treasury_address,
+            fee_info.clone(),
+            chain_context,
+            rpc_endpoint,
+            grpc_endpoint,
+            journal,
+            &mut report,
+        )
+        .await;
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+/// The workers of the last run in a journal that haven't been swept yet.
+fn workers_to_recover(steps: &[FanOutStep]) -> Result<Vec<String>> {
+    let derived = steps
+        .iter()
+        .rev()
+        .find_map(|step| match step {
+            FanOutStep::Derived { workers } => Some(workers.clone()),
+            _ => None,
+        })
+        .ok_or_else(|| eyre!("the journal records no workers"))?;
+    let swept: Vec<&str> = steps
+        .iter()
+        .filter_map(|step| match step {
+            FanOutStep::Swept { worker, .. } => Some(worker.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    Ok(derived
+        .into_iter()
+        .filter(|w| !swept.contains(&w.as_str()))
+        .collect())
+}
+
+/// The hashes of `pending` transactions that still aren't included after waiting once more.
+async fn unsettled_hashes(rpc_endpoint: &str, pending: &[PendingTx]) -> Vec<String> {
+    let mut unsettled = Vec::<String>::new();
+    for tx in pending {
+        if wait_for_tx(rpc_endpoint, &tx.hash, &ConfirmationOptions::default())
+            .await
+            .is_err()
+        {
+            unsettled.push(tx.hash.clone());
+        }
+    }
+
+    unsettled
+}
+
+/// Sweeps `worker` back to the treasury, journaling and reporting the outcome.
+#[allow(clippy::too_many_arguments)]
+async fn sweep_worker(
+    worker: &AccountInfo,
+    treasury_address: &str,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+    journal: &Journal,
+    report: &mut WorkerReport,
+) {
+    let result = sweep(
+        worker,
+        &report.address,
+        treasury_address,
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await;
+    match result {
+        Ok(Some(confirmation)) => {
+            let step = FanOutStep::Swept {
+                worker: report.address.clone(),
+                tx_hash: confirmation.hash.clone(),
+            };
+            report.record(journal, step);
+            report.sweep = Some(confirmation);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            let error = format!("{:#}", e);
+            let step = FanOutStep::SweepFailed {
+                worker: report.address.clone(),
+                error: error.clone(),
+            };
+            report.record(journal, step);
+            report.sweep_error = Some(error);
+        }
+    }
+}
+
+/// Sends everything `worker` holds, less the fee, back to the treasury. Returns `None` if there
+/// is nothing worth sweeping.
+async fn sweep(
+    worker: &AccountInfo,
+    worker_address: &str,
+    treasury_address: &str,
+    fee_info: FeeInfo,
+    chain_context: &Context,
+    rpc_endpoint: &str,
+    grpc_endpoint: &str,
+) -> Result<Option<TxConfirmation>> {
+    let balances = grpc::balances(grpc_endpoint, worker_address, None).await?;
+    let amount = sweep_coins(&balances, &fee_info.get_fee())?;
+    if amount.is_empty() {
+        return Ok(None);
+    }
+
+    let msg = MsgSend {
+        from_address: AccountId::from_str(worker_address)?,
+        to_address: AccountId::from_str(treasury_address)?,
+        amount,
+    }
+    .to_any()?;
+    let confirmation = sign_and_broadcast(
+        worker,
+        vec![msg],
+        fee_info,
+        chain_context,
+        rpc_endpoint,
+        grpc_endpoint,
+    )
+    .await?;
+
+    Ok(Some(confirmation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon
+                            abandon abandon abandon abandon abandon abandon abandon abandon
+                            abandon abandon abandon abandon abandon abandon abandon art";
+
+    fn payment(recipient: &str, amount: u64) -> Payment {
+        Payment {
+            recipient: recipient.to_string(),
+            amount,
+            denom: "uatom".to_string(),
+        }
+    }
+
+    #[test]
+    fn derives_workers_deterministically() {
+        let workers = FanOut::new(MNEMONIC, 2, 10).derive_workers().unwrap();
+        let addresses: Vec<String> = workers
+            .iter()
+            .map(|w| w.address("cosmos").unwrap())
+            .collect();
+
+        assert_eq!(
+            addresses[0],
+            "cosmos1r5v5srda7xfth3hn2s26txvrcrntldjumt8mhl"
+        );
+        assert_ne!(addresses[0], addresses[1]);
+    }
+
+    #[test]
+    fn funds_each_shard_plus_fees() {
+        let payments: Vec<Payment> = (1..=5).map(|i| payment("cosmos1recipient", i)).collect();
+        let shards = shard_payments(payments, 2);
+        let workers = vec!["worker0".to_string(), "worker1".to_string()];
+        let fee = Coin {
+            denom: Denom::from_str("uatom").unwrap(),
+            amount: 100,
+        };
+        let funding = funding_payments(&workers, &shards, &fee, 2).unwrap();
+
+        assert_eq!(shards.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 2]);
+        // 1 + 2 + 3 paid in two batches and a sweep, then 4 + 5 in one batch and a sweep
+        assert_eq!(funding[0].amount, 306);
+        assert_eq!(funding[1].amount, 209);
+
+        let balances = vec![ProtoCoin {
+            denom: "uatom".to_string(),
+            amount: "150".to_string(),
+        }];
+        assert_eq!(sweep_coins(&balances, &fee).unwrap()[0].amount, 50);
+    }
+
+    #[test]
+    fn recovers_workers_not_yet_swept() {
+        let steps = vec![
+            FanOutStep::Derived {
+                workers: vec!["worker0".to_string(), "worker1".to_string()],
+            },
+            FanOutStep::Funded {
+                tx_hash: "AA".to_string(),
+            },
+            FanOutStep::Swept {
+                worker: "worker0".to_string(),
+                tx_hash: "BB".to_string(),
+            },
+            FanOutStep::SweepFailed {
+                worker: "worker1".to_string(),
+                error: "timed out".to_string(),
+            },
+        ];
+
+        assert_eq!(workers_to_recover(&steps).unwrap(), vec!["worker1"]);
+        assert!(workers_to_recover(&steps[1..]).is_err());
+    }
+}